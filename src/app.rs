@@ -1,70 +1,293 @@
-use color_eyre::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use color_eyre::{Result, eyre::eyre};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use ratatui::{DefaultTerminal, Frame};
 
+use rand::{Rng, seq::SliceRandom};
 use rodio::{OutputStream, Sink};
 use std::{
-    collections::VecDeque,
-    path::PathBuf,
-    sync::{Arc, Mutex},
-    time::Duration,
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 use crate::{
-    player::{self, Status, enqueue_dir, enqueue_track},
+    cache,
+    convert::{Conversion, ConversionMark, ConversionState},
+    eq::{self, BAND_COUNT, Preset},
+    gain::{self, GainMode, GainTags, PREAMP_LIMIT},
+    library::{self, PlayStats, Rescan, TrackInfo},
+    player::{self, Chapter, Effects, Status, enqueue_dir, enqueue_track},
+    playlist::{self, Entry, Saved},
+    resume::{self, ResumePoint},
+    scan::Scan,
+    session::{self, Session},
+    smart::{self, Rules},
+    tempo::{MAX_SPEED, MIN_SPEED, SPEED_STEP, Tempo},
     ui,
 };
 
 pub struct App {
-    pub _stream: OutputStream,
+    pub stream: OutputStream,
     pub sink: Arc<Mutex<Sink>>,
     pub status: player::Status,
     pub info: Vec<String>,
     pub track_path: Option<PathBuf>,
     pub track_queue: VecDeque<PathBuf>,
+    pub queue_cursor: usize,
+    // The entry under the cursor, followed when entries ahead of it get played or removed.
+    pub queue_selected: Option<PathBuf>,
+    // Titles and durations given by imported playlists, shown in the queue and kept on export.
+    pub playlist_entries: HashMap<PathBuf, Entry>,
+    // Names of the playlists saved in the data directory.
+    pub playlists: Vec<Saved>,
+    pub playlist_cursor: usize,
+    pub playlist_edit: Option<PlaylistEdit>,
     pub track_pos: Option<Duration>,
     pub track_duration: Option<Duration>,
+    // Sink and track positions when the speed last changed during the current track.
+    pub speed_anchor: Option<(Duration, Duration)>,
     pub volume: f32,
-    pub looping: bool,
+    pub gain_mode: GainMode,
+    pub preamp: f32,
+    pub gain_tags: Option<GainTags>,
+    pub fading_gain: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+    // The queue entry picked to play next while shuffling, it stays where it is in the queue.
+    pub shuffle_next: Option<PathBuf>,
+    // Tracks started in the current round, not picked again until every queued track has been.
+    pub shuffle_played: HashSet<PathBuf>,
+    // Tracks played before the current one, most recent last.
+    pub history: VecDeque<PathBuf>,
+    // Last positions in tracks at least `resume_threshold` long, resumed when they are loaded again.
+    pub resume_points: HashMap<PathBuf, ResumePoint>,
+    pub resume_threshold: Duration,
+    // When each track was last played and how often, for smart playlist rules.
+    pub play_stats: HashMap<PathBuf, PlayStats>,
+    // Set once the current track has actually played, loading a track doesn't count as a play.
+    pub play_counted: bool,
+    pub ab_repeat: AbRepeat,
+    pub gapless: bool,
+    pub preload: Option<Preload>,
+    pub crossfade: bool,
+    pub crossfade_dur: Duration,
+    pub fading_sink: Option<Arc<Mutex<Sink>>>,
+    pub conversion: Option<Conversion>,
+    pub streaming: bool,
+    pub track_streamed: bool,
+    pub lookahead: Vec<Conversion>,
+    pub conversion_marks: HashMap<PathBuf, ConversionMark>,
+    pub scan: Option<Scan>,
+    // The indexed library, kept up to date by a rescan on startup and on request.
+    pub library: Vec<TrackInfo>,
+    pub library_rescan: Option<Receiver<Rescan>>,
+    pub panel: Panel,
+    pub prompt: Option<Prompt>,
+    pub seek_step: Duration,
+    // Which page of the player's controls is shown, taken modulo the page count when drawn.
+    pub controls_page: usize,
+    // Chapters of the track they were read from, read the first time a chapter key is used.
+    pub chapters: Option<(PathBuf, Vec<Chapter>)>,
+    pub effects: Effects,
+    pub eq_presets: Vec<Preset>,
+    // The preset the bands currently match, None once a band has been adjusted by hand.
+    pub eq_preset: Option<usize>,
+    pub eq_band: usize,
+    pub session_saved: Instant,
     pub exit: bool,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    // Plays the current track over and over.
+    One,
+    // Puts every finished track back at the end of the queue.
+    Queue,
+}
+
+impl RepeatMode {
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Queue,
+            RepeatMode::Queue => RepeatMode::Off,
+        }
+    }
+}
+
+// A segment of the current track played over and over, set one point at a time.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AbRepeat {
+    Off,
+    Start(Duration),
+    Loop(Duration, Duration),
+}
+
+// Text being typed in by the user, confirmed with Enter.
+pub struct Prompt {
+    pub action: PromptAction,
+    pub input: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PromptAction {
+    GoTo,
+    NewPlaylist,
+    NewSmartPlaylist,
+    RenamePlaylist,
+    DeletePlaylist,
+    EditRules,
+    SaveEqPreset,
+}
+
+impl PromptAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PromptAction::GoTo => "Go to (1:23:45, 50%, +90s)",
+            PromptAction::NewPlaylist => "New playlist name",
+            PromptAction::NewSmartPlaylist => "New smart playlist name",
+            PromptAction::RenamePlaylist => "Rename playlist to",
+            PromptAction::DeletePlaylist => "Delete this playlist? (y to confirm)",
+            PromptAction::EditRules => "Rules (genre = \"Jazz\" AND year < 1970, rating >= 4)",
+            PromptAction::SaveEqPreset => "Save EQ preset as",
+        }
+    }
+}
+
+// The entries of a saved playlist opened for editing, written back after every change.
+pub struct PlaylistEdit {
+    pub name: String,
+    pub entries: Vec<Entry>,
+    pub cursor: usize,
+}
+
+// The panel taking key presses before the player does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Panel {
+    Player,
+    Equalizer,
+    History,
+    Queue,
+    Playlists,
+}
+
+// A track appended to the sink ahead of time while gapless playback is on.
+pub struct Preload {
+    pub path: PathBuf,
+    pub from_queue: bool,
+}
+
+// How long before the end of the current track the next one gets appended to the sink.
+const GAPLESS_PRELOAD: Duration = Duration::from_secs(5);
+
+// How many of the upcoming queue entries that need converting get converted ahead of time.
+const LOOKAHEAD: usize = 2;
+
+// Shift+arrow seeks take steps of this size, adjustable with Shift+Up/Down.
+const SEEK_STEP_INCREMENT: Duration = Duration::from_secs(15);
+const SEEK_STEP_MAX: Duration = Duration::from_secs(600);
+// A chapter starting this close after the position is taken to be the one already playing,
+// so the next chapter key doesn't land on it again.
+const CHAPTER_MARGIN: Duration = Duration::from_secs(1);
+
+const HISTORY_LIMIT: usize = 100;
+// Going back past this far into a track restarts it instead, like hardware players do.
+const PREVIOUS_RESTART: Duration = Duration::from_secs(3);
+
+// The session is saved on exit and this often while running, in case the terminal gets closed.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const CROSSFADE_STEP: Duration = Duration::from_secs(1);
+const CROSSFADE_MAX: Duration = Duration::from_secs(12);
+
 impl App {
     pub fn new() -> Self {
         let (stream, sink) = player::get_sink().expect("Error creating sink");
-        Self {
-            _stream: stream,
+        let mut eq_presets = eq::builtin_presets();
+        eq_presets.extend(eq::load_user_presets());
+        let mut app = Self {
+            stream,
             sink: Arc::new(Mutex::new(sink)),
             status: Status::Idle,
             info: vec![String::new()],
             track_path: None,
             track_queue: VecDeque::new(),
+            queue_cursor: 0,
+            queue_selected: None,
+            playlist_entries: HashMap::new(),
+            playlists: playlist::list_saved().unwrap_or_default(),
+            playlist_cursor: 0,
+            playlist_edit: None,
             track_pos: None,
             track_duration: None,
+            speed_anchor: None,
             volume: 1.0,
-            looping: false,
+            gain_mode: GainMode::Track,
+            preamp: 0.0,
+            gain_tags: None,
+            fading_gain: 1.0,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+            shuffle_next: None,
+            shuffle_played: HashSet::new(),
+            history: VecDeque::new(),
+            resume_points: resume::load(),
+            resume_threshold: resume::load_threshold(),
+            play_stats: library::load_stats(),
+            play_counted: true,
+            ab_repeat: AbRepeat::Off,
+            gapless: false,
+            preload: None,
+            crossfade: false,
+            crossfade_dur: Duration::from_secs(5),
+            fading_sink: None,
+            conversion: None,
+            streaming: false,
+            track_streamed: false,
+            lookahead: Vec::new(),
+            conversion_marks: HashMap::new(),
+            scan: None,
+            library: library::load_index(),
+            library_rescan: None,
+            panel: Panel::Player,
+            prompt: None,
+            seek_step: Duration::from_secs(30),
+            controls_page: 0,
+            chapters: None,
+            effects: Effects {
+                eq: Arc::new(Mutex::new([0.0; BAND_COUNT])),
+                tempo: Arc::new(Mutex::new(Tempo::default())),
+            },
+            eq_presets,
+            eq_preset: Some(0),
+            eq_band: 0,
+            session_saved: Instant::now(),
             exit: false,
-        }
+        };
+        app.restore_session();
+        app.start_library_rescan();
+
+        app
     }
 
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
         while !self.exit {
-            self.update_logic(terminal);
+            self.update_logic();
             terminal.draw(|frame| self.draw(frame))?;
-            self.handle_events(terminal)?;
+            self.handle_events()?;
         }
         Ok(())
     }
 
-    fn refresh_frame(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
-        self.update_logic(terminal);
-        terminal.draw(|frame| self.draw(frame))?;
-
-        Ok(())
-    }
-
-    fn update_logic(&mut self, terminal: &mut DefaultTerminal) {
-        let mut err: Option<color_eyre::eyre::ErrReport> = None;
+    fn update_logic(&mut self) {
+        // Reloaded once the sink lock is released, loading locks the sink itself
+        let mut reload: Option<PathBuf> = None;
         {
             // Get sink
             let sink = self.sink.lock().unwrap();
@@ -82,23 +305,25 @@ impl App {
             }
 
             // Get track position
-            self.track_pos = Some(sink.get_pos());
+            self.track_pos = Some(self.track_time(sink.get_pos()));
 
             // If path, duration, and position are not None,
             // If sink is empty or the track is within 3 seconds away from ending
-            // If looping is on, load the same track
-            // Else, load next track in queue.
+            // If repeating one, load the same track
+            // If repeating the queue, put the track back at its end
+            // Then load next track in queue.
 
             {
                 if let (Some(path), Some(dur), Some(pos)) =
                     (&self.track_path, self.track_duration, self.track_pos)
                 {
                     if sink.empty() && dur.saturating_sub(pos) < Duration::from_secs(3) {
-                        if self.looping {
-                            if let Err(e) = player::load_track(&self.sink, path) {
-                                err = Some(e);
-                            }
+                        if self.repeat == RepeatMode::One {
+                            reload = Some(path.clone());
                         } else {
+                            if self.repeat == RepeatMode::Queue {
+                                self.track_queue.push_back(path.clone());
+                            }
                             self.track_pos = None;
                             self.track_duration = None;
                             self.status = Status::Idle;
@@ -107,12 +332,48 @@ impl App {
                 }
             }
         }
-        if let Some(e) = err {
-            self.display_info(e.to_string().as_str())
+        self.update_resume_point();
+
+        if let Some(path) = reload {
+            match self.load(&path, true) {
+                Ok(()) => self.play_counted = false,
+                Err(e) => self.display_info(e.to_string().as_str()),
+            }
+        }
+        // Tracks restored paused on startup only count once they are unpaused
+        if !self.play_counted
+            && self.status == Status::Playing
+            && let Some(path) = self.track_path.clone()
+        {
+            self.count_play(&path);
+            self.play_counted = true;
+        }
+
+        // Jump back to A once playback reaches B
+        if let AbRepeat::Loop(start, end) = self.ab_repeat
+            && self.status == Status::Playing
+            && self.track_pos.is_some_and(|pos| pos >= end)
+        {
+            self.seek_to(start);
+        }
+
+        self.update_shuffle();
+        self.update_conversion();
+        self.update_lookahead();
+        self.update_gapless();
+        self.update_crossfade();
+        self.update_scan();
+        self.update_library_rescan();
+        self.update_queue_cursor();
+
+        if self.session_saved.elapsed() >= SESSION_SAVE_INTERVAL {
+            self.save_session();
         }
 
-        if self.status == Status::Idle && !self.track_queue.is_empty() {
-            self.play_next_track(terminal);
+        // Wait for a running conversion instead of skipping past the track it belongs to
+        if self.status == Status::Idle && !self.track_queue.is_empty() && self.conversion.is_none()
+        {
+            self.play_next_track();
         }
     }
 
@@ -121,13 +382,13 @@ impl App {
         ui::render(self, frame);
     }
 
-    fn handle_events(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+    fn handle_events(&mut self) -> Result<()> {
         if event::poll(Duration::from_millis(16))? {
             match event::read()? {
                 // it's important to check that the event is a key press event as
                 // crossterm also emits key release and repeat events on Windows.
                 Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                    self.handle_key_event(key_event, terminal)
+                    self.handle_key_event(key_event)
                 }
                 _ => {}
             };
@@ -135,35 +396,37 @@ impl App {
         Ok(())
     }
 
-    fn handle_key_event(&mut self, key_event: KeyEvent, terminal: &mut DefaultTerminal) {
+    fn handle_key_event(&mut self, key_event: KeyEvent) {
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key_event.code);
+            return;
+        }
+        if self.panel == Panel::Equalizer && self.handle_eq_key(key_event.code) {
+            return;
+        }
+        if self.panel == Panel::Queue && self.handle_queue_key(key_event) {
+            return;
+        }
+        if self.panel == Panel::Playlists && self.handle_playlists_key(key_event) {
+            return;
+        }
+        if self.panel == Panel::History
+            && matches!(key_event.code, KeyCode::Esc | KeyCode::Char('y'))
+        {
+            self.panel = Panel::Player;
+            return;
+        }
+
+        let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
         match key_event.code {
             KeyCode::Esc => self.exit(),
             KeyCode::Char('n') => {
                 if let Some(path) = player::choose_file() {
-                    match player::is_rodio_supported(&path) {
-                        Ok(condition) => {
-                            if !condition {
-                                self.display_info("Converting format and normalizing volume...");
-
-                                self.refresh_frame(terminal)
-                                    .expect("Error refreshing frame");
-                                player::convert_format(&path);
-                            }
-                        }
-                        Err(e) => self.display_info(e.to_string().as_str()),
-                    }
-
-                    if let Err(e) = player::load_track(&self.sink, &path) {
-                        self.display_info(e.to_string().as_str())
-                    }
-                    self.track_path = Some(path);
-                    self.track_duration =
-                        player::get_track_duration(self.track_path.as_ref().unwrap()).ok();
-
-                    self.stop_info_display();
+                    self.play_track(path);
                 }
             }
             KeyCode::Char(' ') => {
+                self.finish_crossfade();
                 let sink = self.sink.lock().unwrap();
                 if self.status == Status::Playing {
                     sink.pause();
@@ -172,39 +435,115 @@ impl App {
                 }
             }
             KeyCode::Char('s') => {
-                self.play_next_track(terminal);
+                // A skipped track comes around again like a finished one
+                if self.repeat == RepeatMode::Queue
+                    && !self.track_queue.is_empty()
+                    && let Some(path) = self.track_path.clone()
+                {
+                    self.track_queue.push_back(path);
+                }
+                self.play_next_track();
+            }
+            KeyCode::Char('c') => {
+                if let Some(conversion) = self.conversion.take() {
+                    conversion.cancel();
+                    self.display_info("Conversion cancelled");
+                }
+            }
+            KeyCode::Up if shift => {
+                self.seek_step = (self.seek_step + SEEK_STEP_INCREMENT).min(SEEK_STEP_MAX);
+            }
+            KeyCode::Down if shift => {
+                self.seek_step = self
+                    .seek_step
+                    .saturating_sub(SEEK_STEP_INCREMENT)
+                    .max(SEEK_STEP_INCREMENT);
             }
             KeyCode::Up => {
-                player::increase_volume(&self.sink, 0.05);
-                self.volume = self.sink.lock().unwrap().volume();
+                self.volume = player::increase_volume(self.volume, 0.05);
+                self.apply_volume();
             }
             KeyCode::Down => {
-                player::decrease_volume(&self.sink, 0.05);
-                self.volume = self.sink.lock().unwrap().volume();
+                self.volume = player::decrease_volume(self.volume, 0.05);
+                self.apply_volume();
             }
-            KeyCode::Right => {
-                if let Some(track_dur) = &self.track_duration {
-                    if self.track_path.is_some() {
-                        player::forward(&self.sink, track_dur, Duration::from_secs(5));
-                    }
+            KeyCode::Right if shift => self.forward(self.seek_step),
+            KeyCode::Left if shift => self.rewind(self.seek_step),
+            KeyCode::Right => self.forward(Duration::from_secs(5)),
+            KeyCode::Left => self.rewind(Duration::from_secs(5)),
+            KeyCode::Char('j') => {
+                self.prompt = Some(Prompt {
+                    action: PromptAction::GoTo,
+                    input: String::new(),
+                });
+            }
+            // 0-9 jump to 0%-90% of the track
+            KeyCode::Char(digit @ '0'..='9') => {
+                if let Some(track_dur) = self.track_duration {
+                    let tenths = digit.to_digit(10).unwrap_or(0);
+                    self.seek_to(track_dur.mul_f32(tenths as f32 / 10.0));
                 }
             }
-            KeyCode::Left => {
-                if let Some(track) = self.track_path.clone() {
-                    if self.track_path.is_some() {
-                        if let Err(e) = player::rewind(&self.sink, &track, Duration::from_secs(5)) {
-                            self.display_info(e.to_string().as_str())
-                        };
-                        self.track_duration = player::get_track_duration(&track).ok();
+            KeyCode::PageDown => self.jump_chapter(true),
+            KeyCode::PageUp => self.jump_chapter(false),
+            KeyCode::Char('b') => {
+                let pos = self.track_pos.unwrap_or_default();
+                self.ab_repeat = match self.ab_repeat {
+                    _ if self.track_path.is_none() => AbRepeat::Off,
+                    AbRepeat::Off => AbRepeat::Start(pos),
+                    AbRepeat::Start(start) if pos > start => AbRepeat::Loop(start, pos),
+                    AbRepeat::Start(start) => {
+                        self.display_info("Point B has to come after point A");
+                        AbRepeat::Start(start)
                     }
-                }
+                    AbRepeat::Loop(..) => AbRepeat::Off,
+                };
+            }
+            KeyCode::Char('?') => {
+                self.controls_page = self.controls_page.wrapping_add(1);
             }
             KeyCode::Char('l') => {
-                if self.looping {
-                    self.looping = false;
-                } else {
-                    self.looping = true;
-                }
+                self.repeat = self.repeat.next();
+            }
+            KeyCode::Char('h') => {
+                self.shuffle = !self.shuffle;
+                self.shuffle_next = None;
+                self.shuffle_played.clear();
+            }
+            KeyCode::Char('H') => {
+                self.track_queue.make_contiguous().shuffle(&mut rand::rng());
+                self.shuffle_next = None;
+                self.display_info("Queue shuffled");
+            }
+            KeyCode::Char('r') => {
+                self.gain_mode = self.gain_mode.next();
+                self.apply_volume();
+            }
+            KeyCode::Char('-') => {
+                self.preamp = (self.preamp - 1.0).max(-PREAMP_LIMIT);
+                self.apply_volume();
+            }
+            KeyCode::Char('=') => {
+                self.preamp = (self.preamp + 1.0).min(PREAMP_LIMIT);
+                self.apply_volume();
+            }
+            KeyCode::Char('m') => {
+                self.streaming = !self.streaming;
+            }
+            KeyCode::Char('g') => {
+                self.gapless = !self.gapless;
+            }
+            KeyCode::Char('x') => {
+                self.crossfade = !self.crossfade;
+            }
+            KeyCode::Char('[') => {
+                self.crossfade_dur = self
+                    .crossfade_dur
+                    .saturating_sub(CROSSFADE_STEP)
+                    .max(CROSSFADE_STEP);
+            }
+            KeyCode::Char(']') => {
+                self.crossfade_dur = (self.crossfade_dur + CROSSFADE_STEP).min(CROSSFADE_MAX);
             }
             KeyCode::Char('q') => {
                 if let Some(path_vec) = player::choose_multiple_files() {
@@ -216,51 +555,1510 @@ impl App {
                     enqueue_dir(dir, &mut self.track_queue);
                 }
             }
+            KeyCode::Char('o') => {
+                if let Some(path) = player::choose_playlist() {
+                    self.enqueue_playlist(&path);
+                }
+            }
+            KeyCode::Char('w') => {
+                if let Some(path) = player::choose_playlist_destination() {
+                    self.save_playlist(&path);
+                }
+            }
+            KeyCode::Char(',') => {
+                let speed = self.tempo().speed - SPEED_STEP;
+                self.set_speed(speed);
+            }
+            KeyCode::Char('.') => {
+                let speed = self.tempo().speed + SPEED_STEP;
+                self.set_speed(speed);
+            }
+            KeyCode::Char('p') => {
+                let mut tempo = self.tempo();
+                tempo.preserve_pitch = !tempo.preserve_pitch;
+                self.set_tempo(tempo);
+            }
+            KeyCode::Char('e') => {
+                self.panel = Panel::Equalizer;
+            }
+            KeyCode::Char('y') => {
+                self.panel = Panel::History;
+            }
+            KeyCode::Tab => {
+                self.panel = Panel::Queue;
+            }
+            KeyCode::Char('v') => {
+                self.panel = Panel::Playlists;
+            }
+            KeyCode::Char('z') => {
+                self.play_previous_track();
+            }
+            KeyCode::Char('a') => {
+                if self.scan.is_some() {
+                    self.display_info("A loudness scan is already running");
+                } else if let Some(dir) = player::choose_dir() {
+                    self.start_scan(dir);
+                }
+            }
+            KeyCode::Char('u') => {
+                if self.library_rescan.is_some() {
+                    self.display_info("The library is already being rescanned");
+                } else {
+                    self.start_library_rescan();
+                    self.display_info("Rescanning the library");
+                }
+            }
             _ => {}
         }
     }
 
-    fn exit(&mut self) {
-        self.exit = true;
+    fn handle_prompt_key(&mut self, code: KeyCode) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+
+        match code {
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.run_prompt(prompt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn run_prompt(&mut self, prompt: Prompt) {
+        match prompt.action {
+            PromptAction::GoTo => {
+                if self.track_path.is_none() {
+                    return;
+                }
+                let current_pos = self.track_pos.unwrap_or_default();
+                match player::seek_target(&prompt.input, current_pos, self.track_duration) {
+                    Ok(target) => self.seek_to(target),
+                    Err(e) => self.display_info(e.to_string().as_str()),
+                }
+            }
+            PromptAction::NewPlaylist | PromptAction::NewSmartPlaylist => {
+                let smart = prompt.action == PromptAction::NewSmartPlaylist;
+                let saved = Saved::new(&prompt.input, smart);
+                if let Err(e) = playlist::create_saved(&saved) {
+                    self.display_info(e.to_string().as_str());
+                    return;
+                }
+                self.refresh_playlists(Some(&saved));
+                // A smart playlist is of little use before it has rules
+                if smart {
+                    self.prompt = Some(Prompt {
+                        action: PromptAction::EditRules,
+                        input: String::new(),
+                    });
+                }
+            }
+            PromptAction::RenamePlaylist => {
+                let Some(saved) = self.selected_playlist() else {
+                    return;
+                };
+                let renamed = Saved::new(&prompt.input, saved.smart);
+                if renamed == saved {
+                    return;
+                }
+                match playlist::rename_saved(&saved, &renamed.name) {
+                    Ok(()) => self.refresh_playlists(Some(&renamed)),
+                    Err(e) => self.display_info(e.to_string().as_str()),
+                }
+            }
+            PromptAction::DeletePlaylist => {
+                let Some(saved) = self.selected_playlist() else {
+                    return;
+                };
+                if !prompt.input.trim().eq_ignore_ascii_case("y") {
+                    return;
+                }
+                match playlist::delete_saved(&saved) {
+                    Ok(()) => self.refresh_playlists(None),
+                    Err(e) => self.display_info(e.to_string().as_str()),
+                }
+            }
+            PromptAction::EditRules => {
+                let Some(saved) = self.selected_playlist().filter(|saved| saved.smart) else {
+                    return;
+                };
+                // Rules that don't parse stay in the prompt to be fixed
+                if let Err(e) = Rules::parse(&prompt.input) {
+                    self.display_info(format!("Invalid rules: {e}").as_str());
+                    self.prompt = Some(prompt);
+                    return;
+                }
+                match saved
+                    .path()
+                    .and_then(|path| smart::save(&path, &prompt.input))
+                {
+                    Ok(()) => self.display_info(format!("Rules of {} saved", saved.name).as_str()),
+                    Err(e) => {
+                        self.display_info(format!("Cannot save {}: {e}", saved.name).as_str())
+                    }
+                }
+            }
+            PromptAction::SaveEqPreset => self.save_eq_preset(prompt.input.trim()),
+        }
     }
 
-    pub fn track_pos_as_str(&self) -> String {
-        let track_pos = self.track_pos.unwrap_or(Duration::from_secs(0));
-        let sec = track_pos.as_secs() % 60;
-        let min = track_pos.as_secs() / 60;
+    // Keys handled while the playlists panel has focus, returns false to pass the key on.
+    // While a playlist is open for editing the keys apply to its entries instead.
+    fn handle_playlists_key(&mut self, key_event: KeyEvent) -> bool {
+        if self.playlist_edit.is_some() {
+            return self.handle_playlist_edit_key(key_event);
+        }
 
-        format!("{:02}:{:02}", min, sec)
+        let last = self.playlists.len().saturating_sub(1);
+        let open_prompt = |action, input: String| Some(Prompt { action, input });
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Tab => self.panel = Panel::Player,
+            KeyCode::Up => self.playlist_cursor = self.playlist_cursor.saturating_sub(1),
+            KeyCode::Down => self.playlist_cursor = (self.playlist_cursor + 1).min(last),
+            KeyCode::Enter => self.open_saved_playlist(true),
+            KeyCode::Char('a') => self.open_saved_playlist(false),
+            KeyCode::Right | KeyCode::Char('e') => self.edit_saved_playlist(),
+            KeyCode::Char('n') => {
+                self.prompt = open_prompt(PromptAction::NewPlaylist, String::new());
+            }
+            KeyCode::Char('s') => {
+                self.prompt = open_prompt(PromptAction::NewSmartPlaylist, String::new());
+            }
+            KeyCode::Char('r') => {
+                if let Some(saved) = self.selected_playlist() {
+                    self.prompt = open_prompt(PromptAction::RenamePlaylist, saved.name);
+                }
+            }
+            KeyCode::Delete => {
+                if self.selected_playlist().is_some() {
+                    self.prompt = open_prompt(PromptAction::DeletePlaylist, String::new());
+                }
+            }
+            KeyCode::Char('c') => {
+                let tracks: Vec<PathBuf> = self.track_path.iter().cloned().collect();
+                self.append_to_saved_playlist(tracks);
+            }
+            KeyCode::Char('q') => {
+                let tracks: Vec<PathBuf> = self.track_queue.iter().cloned().collect();
+                self.append_to_saved_playlist(tracks);
+            }
+            _ => return false,
+        }
+        true
     }
 
-    fn play_next_track(&mut self, terminal: &mut DefaultTerminal) {
-        let next_track = match self.track_queue.pop_front() {
-            Some(path) => path,
-            None => {
+    fn handle_playlist_edit_key(&mut self, key_event: KeyEvent) -> bool {
+        let Some(edit) = &mut self.playlist_edit else {
+            return false;
+        };
+        let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+        let last = edit.entries.len().saturating_sub(1);
+
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Left => {
+                self.playlist_edit = None;
+                return true;
+            }
+            KeyCode::Up if shift && edit.cursor > 0 && edit.cursor <= last => {
+                edit.entries.swap(edit.cursor, edit.cursor - 1);
+                edit.cursor -= 1;
+            }
+            KeyCode::Down if shift && edit.cursor < last => {
+                edit.entries.swap(edit.cursor, edit.cursor + 1);
+                edit.cursor += 1;
+            }
+            KeyCode::Delete | KeyCode::Backspace if !edit.entries.is_empty() => {
+                edit.entries.remove(edit.cursor);
+                edit.cursor = edit.cursor.min(edit.entries.len().saturating_sub(1));
+            }
+            KeyCode::Up => {
+                edit.cursor = edit.cursor.saturating_sub(1);
+                return true;
+            }
+            KeyCode::Down => {
+                edit.cursor = (edit.cursor + 1).min(last);
+                return true;
+            }
+            KeyCode::Enter => {
+                if let Some(entry) = edit.entries.get(edit.cursor).cloned() {
+                    match entry.path.is_file() {
+                        true => self.play_track(entry.path),
+                        false => self.display_info("That file no longer exists"),
+                    }
+                }
+                return true;
+            }
+            KeyCode::Tab => {
+                self.panel = Panel::Player;
+                return true;
+            }
+            _ => return false,
+        }
+
+        // Only changes to the entries get here
+        let (saved, entries) = (Saved::new(&edit.name, false), edit.entries.clone());
+        self.write_saved_playlist(&saved, &entries);
+        true
+    }
+
+    fn selected_playlist(&self) -> Option<Saved> {
+        self.playlists.get(self.playlist_cursor).cloned()
+    }
+
+    // Reloads the list of saved playlists, keeping the cursor on `select` when given.
+    fn refresh_playlists(&mut self, select: Option<&Saved>) {
+        match playlist::list_saved() {
+            Ok(playlists) => self.playlists = playlists,
+            Err(e) => self.display_info(format!("Cannot read playlists: {e}").as_str()),
+        }
+        if let Some(idx) = select.and_then(|saved| self.playlists.iter().position(|p| p == saved)) {
+            self.playlist_cursor = idx;
+        }
+        self.playlist_cursor = self
+            .playlist_cursor
+            .min(self.playlists.len().saturating_sub(1));
+    }
+
+    // Puts the selected playlist in the queue, either in place of what is queued or after it.
+    // Smart playlists are evaluated against the library right then.
+    fn open_saved_playlist(&mut self, replace: bool) {
+        let Some(saved) = self.selected_playlist() else {
+            return;
+        };
+        let path = match saved.path() {
+            Ok(path) => path,
+            Err(e) => {
+                self.display_info(e.to_string().as_str());
                 return;
             }
         };
+        let smart_tracks = match saved.smart {
+            true => match self.evaluate_smart_playlist(&path) {
+                Ok(tracks) => Some(tracks),
+                Err(e) => {
+                    self.display_info(format!("Cannot open {}: {e}", saved.name).as_str());
+                    return;
+                }
+            },
+            false => None,
+        };
+
+        if replace {
+            self.track_queue.clear();
+            self.shuffle_next = None;
+        }
+        match smart_tracks {
+            Some(tracks) => {
+                self.display_info(
+                    format!("Queued {} tracks matching {}", tracks.len(), saved.name).as_str(),
+                );
+                self.track_queue.extend(tracks);
+            }
+            None => self.enqueue_playlist(&path),
+        }
+        self.select_queue_entry(self.queue_cursor);
+    }
+
+    fn evaluate_smart_playlist(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let rules = Rules::parse(&smart::load(path)?)?;
+        // Before the first scan has finished there is nothing to pick from
+        if self.library.is_empty() && self.library_rescan.is_some() {
+            return Err(eyre!("the library is still being scanned"));
+        }
 
-        match player::is_rodio_supported(&next_track) {
-            Ok(condition) => {
-                if !condition {
-                    self.display_info("Converting format and normalizing volume...");
+        Ok(smart::evaluate(&rules, &self.library, &self.play_stats))
+    }
 
-                    self.refresh_frame(terminal)
-                        .expect("Error refreshing frame");
-                    player::convert_format(&next_track);
+    // Smart playlists have their rules edited in the prompt instead of a list of entries.
+    fn edit_saved_playlist(&mut self) {
+        let Some(saved) = self.selected_playlist() else {
+            return;
+        };
+        let path = saved.path();
+        if saved.smart {
+            match path.and_then(|path| smart::load(&path)) {
+                Ok(rules) => {
+                    self.prompt = Some(Prompt {
+                        action: PromptAction::EditRules,
+                        input: rules,
+                    })
                 }
+                Err(e) => self.display_info(format!("Cannot read {}: {e}", saved.name).as_str()),
             }
-            Err(e) => self.display_info(e.to_string().as_str()),
+            return;
         }
 
-        if let Err(e) = player::load_track(&self.sink, &next_track) {
-            self.display_info(e.to_string().as_str())
+        match path.and_then(|path| playlist::load(&path)) {
+            Ok(entries) => {
+                self.playlist_edit = Some(PlaylistEdit {
+                    name: saved.name,
+                    entries,
+                    cursor: 0,
+                })
+            }
+            Err(e) => self.display_info(format!("Cannot read {}: {e}", saved.name).as_str()),
+        }
+    }
+
+    fn append_to_saved_playlist(&mut self, tracks: Vec<PathBuf>) {
+        let Some(saved) = self.selected_playlist() else {
+            self.display_info("Create a playlist first");
+            return;
         };
+        if saved.smart {
+            self.display_info("Smart playlists are filled by their rules");
+            return;
+        }
+        if tracks.is_empty() {
+            self.display_info("Nothing to add");
+            return;
+        }
 
-        self.track_path = Some(next_track);
-        self.track_duration = player::get_track_duration(self.track_path.as_ref().unwrap()).ok();
+        let mut entries = match saved.path().and_then(|path| playlist::load(&path)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.display_info(format!("Cannot read {}: {e}", saved.name).as_str());
+                return;
+            }
+        };
+        let count = tracks.len();
+        entries.extend(tracks.iter().map(|track| self.playlist_entry(track)));
 
-        self.stop_info_display();
+        if self.write_saved_playlist(&saved, &entries) {
+            self.display_info(format!("Added {count} tracks to {}", saved.name).as_str());
+        }
+    }
+
+    fn write_saved_playlist(&mut self, saved: &Saved, entries: &[Entry]) -> bool {
+        match saved.path().and_then(|path| playlist::save(&path, entries)) {
+            Ok(()) => true,
+            Err(e) => {
+                self.display_info(format!("Cannot save {}: {e}", saved.name).as_str());
+                false
+            }
+        }
+    }
+
+    // An entry for a track, with its title from the playlist it was queued from if any.
+    fn playlist_entry(&self, track: &PathBuf) -> Entry {
+        let mut entry = self
+            .playlist_entries
+            .get(track)
+            .cloned()
+            .unwrap_or_else(|| Entry::new(track.clone()));
+        entry.duration = player::get_track_duration(track).ok().or(entry.duration);
+        entry
+    }
+
+    // Keys handled by the EQ panel while it is open, returns false to pass the key on.
+    fn handle_eq_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Esc | KeyCode::Char('e') => self.panel = Panel::Player,
+            KeyCode::Left => self.eq_band = self.eq_band.saturating_sub(1),
+            KeyCode::Right => self.eq_band = (self.eq_band + 1).min(BAND_COUNT - 1),
+            KeyCode::Up => self.adjust_eq_band(eq::GAIN_STEP),
+            KeyCode::Down => self.adjust_eq_band(-eq::GAIN_STEP),
+            KeyCode::Char('p') => {
+                let next = self.eq_preset.map_or(0, |idx| idx + 1) % self.eq_presets.len();
+                self.apply_eq_preset(next);
+            }
+            KeyCode::Char('w') => {
+                // Starts from the selected user preset's name, making it easy to save over it
+                let name = self
+                    .eq_preset
+                    .map(|idx| &self.eq_presets[idx])
+                    .filter(|preset| !preset.builtin)
+                    .map(|preset| preset.name.clone())
+                    .unwrap_or_default();
+                self.prompt = Some(Prompt {
+                    action: PromptAction::SaveEqPreset,
+                    input: name,
+                });
+            }
+            KeyCode::Delete => self.delete_eq_preset(),
+            _ => return false,
+        }
+        true
+    }
+
+    // Keys handled while the queue has focus, returns false to pass the key on.
+    fn handle_queue_key(&mut self, key_event: KeyEvent) -> bool {
+        let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+        let last = self.track_queue.len().saturating_sub(1);
+        match key_event.code {
+            KeyCode::Esc => self.panel = Panel::Player,
+            KeyCode::Tab => self.panel = Panel::Playlists,
+            KeyCode::Up if shift => self.move_queue_entry(false),
+            KeyCode::Down if shift => self.move_queue_entry(true),
+            KeyCode::Up => self.select_queue_entry(self.queue_cursor.saturating_sub(1)),
+            KeyCode::Down => self.select_queue_entry(self.queue_cursor + 1),
+            KeyCode::PageUp => self.select_queue_entry(self.queue_cursor.saturating_sub(10)),
+            KeyCode::PageDown => self.select_queue_entry(self.queue_cursor + 10),
+            KeyCode::Home => self.select_queue_entry(0),
+            KeyCode::End => self.select_queue_entry(last),
+            KeyCode::Enter => {
+                if let Some(path) = self.track_queue.remove(self.queue_cursor) {
+                    self.select_queue_entry(self.queue_cursor);
+                    self.play_track(path);
+                }
+            }
+            KeyCode::Char('n') => {
+                if let Some(path) = self.track_queue.remove(self.queue_cursor) {
+                    self.track_queue.push_front(path.clone());
+                    self.mark_shuffle_next(path);
+                    // The cursor stays on its row, so following entries can be picked in turn
+                    self.select_queue_entry(self.queue_cursor);
+                }
+            }
+            KeyCode::Delete | KeyCode::Backspace => {
+                self.track_queue.remove(self.queue_cursor);
+                self.select_queue_entry(self.queue_cursor);
+            }
+            KeyCode::Char('c') => {
+                self.track_queue.clear();
+                self.shuffle_next = None;
+                self.select_queue_entry(0);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn select_queue_entry(&mut self, idx: usize) {
+        self.queue_cursor = idx.min(self.track_queue.len().saturating_sub(1));
+        self.queue_selected = self.track_queue.get(self.queue_cursor).cloned();
+    }
+
+    // Swaps the selected entry with its neighbour, the cursor moves along with it.
+    fn move_queue_entry(&mut self, down: bool) {
+        let idx = self.queue_cursor;
+        let target = match down {
+            true => idx + 1,
+            false => idx.wrapping_sub(1),
+        };
+        if target >= self.track_queue.len() || idx >= self.track_queue.len() {
+            return;
+        }
+
+        self.track_queue.swap(idx, target);
+        if target == 0 {
+            self.mark_shuffle_next(self.track_queue[0].clone());
+        }
+        self.select_queue_entry(target);
+    }
+
+    // While shuffling, keeps an entry moved to the front by hand from being replaced by a random pick.
+    fn mark_shuffle_next(&mut self, path: PathBuf) {
+        if self.shuffle {
+            self.shuffle_next = Some(path);
+        }
+    }
+
+    // Entries come and go at the front as tracks play, so the cursor follows the entry it was on
+    // rather than staying at the same index.
+    fn update_queue_cursor(&mut self) {
+        if let Some(selected) = &self.queue_selected
+            && self.track_queue.get(self.queue_cursor) != Some(selected)
+            && let Some(idx) = self
+                .track_queue
+                .iter()
+                .enumerate()
+                .filter(|(_, path)| *path == selected)
+                .map(|(idx, _)| idx)
+                .min_by_key(|idx| idx.abs_diff(self.queue_cursor))
+        {
+            self.queue_cursor = idx;
+        }
+        self.select_queue_entry(self.queue_cursor);
+    }
+
+    fn adjust_eq_band(&mut self, step: f32) {
+        let mut gains = self.effects.eq.lock().unwrap();
+        let gain = &mut gains[self.eq_band];
+        *gain = (*gain + step).clamp(-eq::GAIN_LIMIT, eq::GAIN_LIMIT);
+        self.eq_preset = None;
+    }
+
+    fn apply_eq_preset(&mut self, idx: usize) {
+        *self.effects.eq.lock().unwrap() = self.eq_presets[idx].gains;
+        self.eq_preset = Some(idx);
+    }
+
+    // Saves the current bands as a user preset, replacing a user preset of the same name.
+    // Built-in presets can't be replaced.
+    fn save_eq_preset(&mut self, name: &str) {
+        if name.is_empty() {
+            self.display_info("An EQ preset needs a name");
+            return;
+        }
+        let existing = self
+            .eq_presets
+            .iter()
+            .position(|preset| preset.name.eq_ignore_ascii_case(name));
+        if existing.is_some_and(|idx| self.eq_presets[idx].builtin) {
+            self.display_info(format!("{name} is a built-in preset, pick another name").as_str());
+            return;
+        }
+
+        let preset = Preset {
+            name: name.to_string(),
+            gains: *self.effects.eq.lock().unwrap(),
+            builtin: false,
+        };
+        let idx = match existing {
+            Some(idx) => {
+                self.eq_presets[idx] = preset;
+                idx
+            }
+            None => {
+                self.eq_presets.push(preset);
+                self.eq_presets.len() - 1
+            }
+        };
+        self.eq_preset = Some(idx);
+
+        let saved = match existing {
+            Some(_) => format!("EQ preset {name} overwritten"),
+            None => format!("EQ preset {name} saved"),
+        };
+        match eq::save_user_presets(&self.eq_presets) {
+            Ok(()) => self.display_info(saved.as_str()),
+            Err(e) => self.display_info(format!("Cannot save EQ presets: {e}").as_str()),
+        }
+    }
+
+    // Removes the selected user preset, the bands stay as they are.
+    fn delete_eq_preset(&mut self) {
+        let Some(idx) = self.eq_preset.filter(|idx| !self.eq_presets[*idx].builtin) else {
+            self.display_info("Only user presets can be deleted");
+            return;
+        };
+        self.eq_presets.remove(idx);
+        self.eq_preset = None;
+
+        match eq::save_user_presets(&self.eq_presets) {
+            Ok(()) => self.display_info("EQ preset deleted"),
+            Err(e) => self.display_info(format!("Cannot save EQ presets: {e}").as_str()),
+        }
+    }
+
+    fn exit(&mut self) {
+        // Make sure no ffmpeg process outlives the player
+        if let Some(conversion) = self.conversion.take() {
+            conversion.cancel();
+        }
+        for conversion in self.lookahead.drain(..) {
+            conversion.cancel();
+        }
+        // A loudness scan may be writing tags into a file
+        if let Some(scan) = self.scan.take() {
+            scan.cancel();
+        }
+        self.save_session();
+        self.exit = true;
+    }
+
+    // Queues files, directories and playlists given on the command line.
+    pub fn enqueue_paths(&mut self, paths: Vec<PathBuf>) {
+        for path in paths {
+            if path.is_dir() {
+                enqueue_dir(path, &mut self.track_queue);
+            } else if playlist::is_playlist(&path) {
+                self.enqueue_playlist(&path);
+            } else if path.is_file() {
+                enqueue_track(vec![path], &mut self.track_queue);
+            } else {
+                self.display_info(format!("{} not found", path.display()).as_str());
+            }
+        }
+    }
+
+    // Entries whose files are missing are left out and listed in the info line.
+    fn enqueue_playlist(&mut self, path: &Path) {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("playlist");
+        let entries = match playlist::load(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.display_info(format!("Cannot read {name}: {e}").as_str());
+                return;
+            }
+        };
+
+        let mut tracks: Vec<PathBuf> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for entry in entries {
+            if !entry.path.is_file() {
+                missing.push(entry.path.display().to_string());
+                continue;
+            }
+            tracks.push(entry.path.clone());
+            if entry.title.is_some() || entry.duration.is_some() {
+                self.playlist_entries.insert(entry.path.clone(), entry);
+            }
+        }
+        // Queued like any other files, which leaves out entries that aren't audio
+        let queue_len = self.track_queue.len();
+        enqueue_track(tracks, &mut self.track_queue);
+        let queued = self.track_queue.len() - queue_len;
+
+        match missing.is_empty() {
+            true => self.display_info(format!("Queued {queued} tracks from {name}").as_str()),
+            false => self.display_info(
+                format!(
+                    "Queued {queued} tracks from {name}, {} not found: {}",
+                    missing.len(),
+                    missing.join(", ")
+                )
+                .as_str(),
+            ),
+        }
+    }
+
+    // Saves the playing track followed by the queue.
+    fn save_playlist(&mut self, path: &Path) {
+        let entries: Vec<Entry> = self
+            .track_path
+            .iter()
+            .chain(self.track_queue.iter())
+            .map(|track| self.playlist_entry(track))
+            .collect();
+
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("playlist");
+        match playlist::save(path, &entries) {
+            Ok(()) => {
+                self.display_info(format!("Saved {} tracks to {name}", entries.len()).as_str())
+            }
+            Err(e) => self.display_info(format!("Cannot save {name}: {e}").as_str()),
+        }
+    }
+
+    fn save_session(&mut self) {
+        self.session_saved = Instant::now();
+
+        // A finished track is not resumed, the queue picks up after it
+        let track = match self.status {
+            Status::Idle => None,
+            _ => self.track_path.clone(),
+        };
+        // Tracks taken off the queue but not playing yet go back at its front
+        let mut queue: Vec<PathBuf> = Vec::new();
+        if let Some(conversion) = &self.conversion {
+            queue.push(conversion.track.clone());
+        }
+        if let Some(preload) = self.preload.as_ref().filter(|preload| preload.from_queue) {
+            queue.push(preload.path.clone());
+        }
+        queue.extend(self.track_queue.iter().cloned());
+
+        let session = Session {
+            track,
+            position: self.track_pos.unwrap_or_default(),
+            queue,
+            volume: self.volume,
+            repeat: self.repeat,
+            shuffle: self.shuffle,
+        };
+        if let Err(e) = session::save(&session) {
+            self.display_info(format!("Cannot save session: {e}").as_str());
+        }
+        if let Err(e) = resume::save(&self.resume_points) {
+            self.display_info(format!("Cannot save resume positions: {e}").as_str());
+        }
+        if let Err(e) = library::save_stats(&self.play_stats) {
+            self.display_info(format!("Cannot save play counts: {e}").as_str());
+        }
+    }
+
+    // Picks up where the last session left off, with its track paused at the saved position.
+    // Files that have gone missing since are dropped.
+    fn restore_session(&mut self) {
+        let Some(session) = session::load() else {
+            return;
+        };
+
+        self.volume = session.volume;
+        self.repeat = session.repeat;
+        self.shuffle = session.shuffle;
+        self.track_queue = session
+            .queue
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+        self.select_queue_entry(0);
+
+        // Loaded straight into a paused sink, so nothing plays before the user unpauses
+        if let Some(track) = session.track.filter(|path| path.exists()) {
+            self.open_track(track, false);
+            if !session.position.is_zero() {
+                self.seek_to(session.position);
+            }
+            self.status = Status::Paused;
+        }
+        self.apply_volume();
+    }
+
+    // Stops a second short of the end rather than running past it.
+    fn forward(&mut self, dur: Duration) {
+        let (Some(pos), Some(track_dur)) = (self.track_pos, self.track_duration) else {
+            return;
+        };
+        let target = (pos + dur).min(track_dur.saturating_sub(Duration::from_secs(1)));
+        if target > pos {
+            self.seek_to(target);
+        }
+    }
+
+    fn rewind(&mut self, dur: Duration) {
+        if let Some(pos) = self.track_pos {
+            self.seek_to(pos.saturating_sub(dur));
+        }
+    }
+
+    // Goes to the start of the next chapter, or back to the start of the current one.
+    // Early in a chapter going back goes to the one before it instead, like the previous key.
+    fn jump_chapter(&mut self, forward: bool) {
+        let Some(track) = self.track_path.clone() else {
+            return;
+        };
+        if self
+            .chapters
+            .as_ref()
+            .is_none_or(|(path, _)| *path != track)
+        {
+            match player::read_chapters(&track) {
+                Ok(chapters) => self.chapters = Some((track, chapters)),
+                Err(e) => {
+                    self.display_info(format!("Cannot read chapters: {e}").as_str());
+                    return;
+                }
+            }
+        }
+        let Some((_, chapters)) = &self.chapters else {
+            return;
+        };
+        if chapters.is_empty() {
+            self.display_info("This track has no chapters");
+            return;
+        }
+
+        let pos = self.track_pos.unwrap_or_default();
+        let found = match forward {
+            true => chapters
+                .iter()
+                .position(|chapter| chapter.start > pos + CHAPTER_MARGIN),
+            false => chapters
+                .iter()
+                .rposition(|chapter| chapter.start + PREVIOUS_RESTART <= pos)
+                .or(Some(0)),
+        };
+        let Some(idx) = found else {
+            self.display_info("This is the last chapter");
+            return;
+        };
+        let start = chapters[idx].start;
+        let info = match &chapters[idx].title {
+            Some(title) => format!("Chapter {}: {title}", idx + 1),
+            None => format!("Chapter {}", idx + 1),
+        };
+
+        self.seek_to(start);
+        self.display_info(info.as_str());
+    }
+
+    fn seek_to(&mut self, target: Duration) {
+        let Some(track) = self.track_path.clone() else {
+            return;
+        };
+        self.finish_crossfade();
+
+        match player::seek(&self.sink, &track, target, &self.effects) {
+            Ok(reopened) => {
+                if reopened {
+                    self.cancel_preload();
+                }
+                // The sink counts from the target at the current speed now
+                self.speed_anchor = None;
+                self.track_pos = Some(target);
+            }
+            Err(e) => self.display_info(e.to_string().as_str()),
+        }
+    }
+
+    pub fn tempo(&self) -> Tempo {
+        *self.effects.tempo.lock().unwrap()
+    }
+
+    // Rounded to the step so repeated presses land on even values.
+    fn set_speed(&mut self, speed: f32) {
+        let mut tempo = self.tempo();
+        tempo.speed = ((speed / SPEED_STEP).round() * SPEED_STEP).clamp(MIN_SPEED, MAX_SPEED);
+        self.set_tempo(tempo);
+    }
+
+    fn set_tempo(&mut self, tempo: Tempo) {
+        // The sink only counts time played, so remember where the track was
+        // when the speed changed and scale from there
+        let sink_pos = self.sink.lock().unwrap().get_pos();
+        self.speed_anchor = Some((sink_pos, self.track_time(sink_pos)));
+        *self.effects.tempo.lock().unwrap() = tempo;
+    }
+
+    // Converts the sink's position into a position in the current track.
+    fn track_time(&self, sink_pos: Duration) -> Duration {
+        let speed = self.tempo().speed;
+        match self.speed_anchor {
+            Some((anchor_sink, anchor_track)) if sink_pos >= anchor_sink => {
+                anchor_track + (sink_pos - anchor_sink).mul_f32(speed)
+            }
+            _ => sink_pos.mul_f32(speed),
+        }
+    }
+
+    // Picks the queue entry to play next while shuffling, without reordering the queue.
+    // Everything taking the next track (gapless, crossfade, lookahead) goes through next_queue_index.
+    fn update_shuffle(&mut self) {
+        if !self.shuffle {
+            return;
+        }
+        // Tracks count as played in the round once they start
+        if let Some(path) = &self.track_path
+            && !self.shuffle_played.contains(path)
+        {
+            self.shuffle_played.insert(path.clone());
+        }
+        if self.track_queue.is_empty() {
+            return;
+        }
+        if let Some(next) = &self.shuffle_next
+            && self.track_queue.contains(next)
+        {
+            return;
+        }
+
+        let unplayed = |played: &HashSet<PathBuf>| -> Vec<usize> {
+            (0..self.track_queue.len())
+                .filter(|idx| !played.contains(&self.track_queue[*idx]))
+                .collect()
+        };
+        let mut candidates = unplayed(&self.shuffle_played);
+        // Every queued track got its turn, start a new round,
+        // leaving out the track that just played unless nothing else is queued
+        if candidates.is_empty() {
+            self.shuffle_played.clear();
+            if let Some(last) = self.track_path.as_ref().or(self.history.back()) {
+                self.shuffle_played.insert(last.clone());
+            }
+            candidates = unplayed(&self.shuffle_played);
+            if candidates.is_empty() {
+                candidates = (0..self.track_queue.len()).collect();
+            }
+        }
+
+        let pick = candidates[rand::rng().random_range(0..candidates.len())];
+        self.shuffle_next = Some(self.track_queue[pick].clone());
+    }
+
+    // Where the next track comes from in the queue: the shuffle pick while shuffling, otherwise the front.
+    fn next_queue_index(&self) -> Option<usize> {
+        if self.shuffle
+            && let Some(next) = &self.shuffle_next
+            && let Some(idx) = self.track_queue.iter().position(|path| path == next)
+        {
+            return Some(idx);
+        }
+        (!self.track_queue.is_empty()).then_some(0)
+    }
+
+    fn take_next_from_queue(&mut self) -> Option<PathBuf> {
+        let idx = self.next_queue_index()?;
+        self.shuffle_next = None;
+        self.track_queue.remove(idx)
+    }
+
+    // Puts a track taken for playback back at the front of the queue, still up next while shuffling.
+    fn return_to_queue(&mut self, path: PathBuf) {
+        self.track_queue.push_front(path.clone());
+        self.mark_shuffle_next(path);
+    }
+
+    // Restarts the current track, or goes back to the one before it near its start.
+    // The current track returns to the front of the queue so skipping leads back to it.
+    fn play_previous_track(&mut self) {
+        let near_start = self.track_pos.is_none_or(|pos| pos <= PREVIOUS_RESTART);
+        if !near_start || self.history.is_empty() {
+            self.seek_to(Duration::ZERO);
+            return;
+        }
+        let Some(previous) = self.history.pop_back() else {
+            return;
+        };
+
+        self.cancel_preload();
+        if let Some(current) = self.track_path.take() {
+            if self.shuffle {
+                self.shuffle_next = Some(current.clone());
+            }
+            self.track_queue.push_front(current);
+        }
+        self.play_track(previous);
+    }
+
+    fn count_play(&mut self, path: &Path) {
+        let stats = self
+            .play_stats
+            .entry(path.to_path_buf())
+            .or_insert(PlayStats {
+                last_played: SystemTime::now(),
+                plays: 0,
+            });
+        stats.last_played = SystemTime::now();
+        stats.plays += 1;
+    }
+
+    fn push_history(&mut self) {
+        let Some(path) = self.track_path.clone() else {
+            return;
+        };
+        if self.history.back() != Some(&path) {
+            self.history.push_back(path);
+        }
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+    }
+
+    fn play_next_track(&mut self) {
+        self.cancel_preload();
+
+        let next_track = match self.take_next_from_queue() {
+            Some(path) => path,
+            None => {
+                return;
+            }
+        };
+
+        let queue = &self.track_queue;
+        self.conversion_marks.retain(|path, _| queue.contains(path));
+
+        self.play_track(next_track);
+    }
+
+    // Plays a track right away, or starts converting it in the background
+    // and plays it once the conversion finishes.
+    fn play_track(&mut self, path: PathBuf) {
+        self.cancel_preload();
+        self.finish_crossfade();
+        if let Some(conversion) = self.conversion.take() {
+            conversion.cancel();
+        }
+
+        if let Err(e) = player::is_rodio_supported(&path) {
+            self.display_info(e.to_string().as_str());
+            return;
+        }
+
+        self.push_history();
+
+        if player::is_playable_now(&path) || self.streaming {
+            self.start_track(path);
+            return;
+        }
+
+        // Take over the lookahead conversion if the track is already being converted
+        let conversion = match self.lookahead.iter().position(|c| c.track == path) {
+            Some(idx) => self.lookahead.remove(idx),
+            None => match cache::cached_path(&path) {
+                Ok(output) => Conversion::start(path, output),
+                Err(e) => {
+                    self.display_info(e.to_string().as_str());
+                    return;
+                }
+            },
+        };
+
+        // Stop the current track, the requested one takes over once it is converted
+        self.sink.lock().unwrap().clear();
+        self.track_path = None;
+        self.ab_repeat = AbRepeat::Off;
+        self.track_pos = None;
+        self.track_duration = None;
+        self.gain_tags = None;
+
+        self.conversion = Some(conversion);
+        self.stop_info_display();
+    }
+
+    fn start_track(&mut self, path: PathBuf) {
+        self.open_track(path, true);
+    }
+
+    fn open_track(&mut self, path: PathBuf, play: bool) {
+        if let Err(e) = self.load(&path, play) {
+            self.display_info(e.to_string().as_str())
+        };
+
+        self.play_counted = false;
+        self.track_path = Some(path);
+        self.ab_repeat = AbRepeat::Off;
+        self.track_duration = player::get_track_duration(self.track_path.as_ref().unwrap()).ok();
+        self.refresh_gain();
+
+        self.stop_info_display();
+        self.resume_saved_position();
+    }
+
+    // Long tracks pick up where they were left, finished ones start over.
+    fn resume_saved_position(&mut self) {
+        let Some(point) = self
+            .track_path
+            .as_ref()
+            .and_then(|path| self.resume_position(path))
+        else {
+            return;
+        };
+
+        self.seek_to(point.position);
+        self.display_info(format!("Resumed at {:.0}%", point.progress() * 100.0).as_str());
+    }
+
+    // The saved point a track would resume from, if it has one it hasn't finished.
+    fn resume_position(&self, path: &Path) -> Option<ResumePoint> {
+        self.resume_points
+            .get(path)
+            .filter(|point| !point.finished && !point.position.is_zero())
+            .copied()
+    }
+
+    fn update_resume_point(&mut self) {
+        let (Some(path), Some(dur), Some(pos)) =
+            (&self.track_path, self.track_duration, self.track_pos)
+        else {
+            return;
+        };
+        if self.status == Status::Idle || dur < self.resume_threshold {
+            return;
+        }
+
+        self.resume_points
+            .insert(path.clone(), ResumePoint::new(pos, dur));
+    }
+
+    // Loads a track into the sink, streaming it through ffmpeg
+    // if rodio cannot decode it and it has not been converted.
+    fn load(&mut self, path: &PathBuf, play: bool) -> Result<()> {
+        self.track_streamed = !player::is_playable_now(path);
+        self.speed_anchor = None;
+        match self.track_streamed {
+            true => player::load_stream(&self.sink, path, &self.effects, play),
+            false => player::load_track(&self.sink, path, &self.effects, play),
+        }
+    }
+
+    fn update_conversion(&mut self) {
+        let Some(conversion) = &self.conversion else {
+            return;
+        };
+
+        match conversion.state() {
+            ConversionState::Running(_) => {}
+            ConversionState::Finished => {
+                let conversion = self.conversion.take().unwrap();
+                if let Err(e) = cache::evict(std::slice::from_ref(&conversion.output)) {
+                    self.display_info(e.to_string().as_str());
+                }
+                self.start_track(conversion.track);
+            }
+            ConversionState::Failed(e) => {
+                self.conversion = None;
+                self.display_info(format!("Conversion failed: {e}").as_str());
+            }
+            ConversionState::Cancelled => {
+                self.conversion = None;
+            }
+        }
+    }
+
+    // Converts the next few queue entries rodio cannot decode while the current track plays,
+    // so they are ready by the time the queue reaches them.
+    fn update_lookahead(&mut self) {
+        let mut finished: Vec<(PathBuf, PathBuf, Option<String>)> = Vec::new();
+        self.lookahead.retain(|conversion| {
+            let (track, output) = (conversion.track.clone(), conversion.output.clone());
+            match conversion.state() {
+                ConversionState::Running(_) => return true,
+                ConversionState::Finished => finished.push((track, output, None)),
+                ConversionState::Failed(e) => finished.push((track, output, Some(e))),
+                ConversionState::Cancelled => {}
+            }
+            false
+        });
+
+        for (track, output, err) in finished {
+            match err {
+                None => {
+                    self.conversion_marks.insert(track, ConversionMark::Ready);
+                    if let Err(e) = cache::evict(std::slice::from_ref(&output)) {
+                        self.display_info(e.to_string().as_str());
+                    }
+                }
+                Some(e) => {
+                    let name = track
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("track");
+                    self.display_info(format!("Converting {name} failed: {e}").as_str());
+                    self.conversion_marks.insert(track, ConversionMark::Failed);
+                }
+            }
+        }
+
+        // Streamed tracks never need converting
+        if self.streaming {
+            return;
+        }
+
+        // The shuffle pick comes first, then the queue in order
+        let next = self.next_queue_index();
+        let order = next
+            .into_iter()
+            .chain((0..self.track_queue.len()).filter(|idx| Some(*idx) != next));
+        let mut upcoming: Vec<PathBuf> = Vec::new();
+        for path in order.map(|idx| &self.track_queue[idx]) {
+            if upcoming.len() == LOOKAHEAD {
+                break;
+            }
+            // Each entry is only probed once, detecting the format reads the file
+            let mark = *self
+                .conversion_marks
+                .entry(path.clone())
+                .or_insert_with(|| match player::is_rodio_supported(path) {
+                    Ok(true) => ConversionMark::Native,
+                    Ok(false) if cache::is_cached(path) => ConversionMark::Ready,
+                    Ok(false) => ConversionMark::Pending,
+                    Err(_) => ConversionMark::Failed,
+                });
+            if mark == ConversionMark::Pending {
+                upcoming.push(path.clone());
+            }
+        }
+
+        for path in upcoming {
+            let converting = self.lookahead.iter().any(|c| c.track == path)
+                || self.conversion.as_ref().is_some_and(|c| c.track == path);
+            if converting {
+                continue;
+            }
+
+            match cache::cached_path(&path) {
+                Ok(output) => self.lookahead.push(Conversion::start(path, output)),
+                Err(e) => {
+                    self.display_info(e.to_string().as_str());
+                    self.conversion_marks.insert(path, ConversionMark::Failed);
+                }
+            }
+        }
+    }
+
+    fn update_gapless(&mut self) {
+        let sink_len = self.sink.lock().unwrap().len();
+
+        // The preloaded source is now the only one left in the sink,
+        // so playback has moved on to it.
+        if sink_len <= 1
+            && let Some(preload) = self.preload.take()
+        {
+            if sink_len == 0 {
+                // Sink got emptied before the preloaded track started, put it back.
+                if preload.from_queue {
+                    self.return_to_queue(preload.path);
+                }
+                return;
+            }
+
+            self.track_duration = player::get_track_duration(&preload.path).ok();
+            self.requeue_finished();
+            self.push_history();
+            self.play_counted = false;
+            self.track_path = Some(preload.path);
+            self.ab_repeat = AbRepeat::Off;
+            self.speed_anchor = None;
+            let sink_pos = self.sink.lock().unwrap().get_pos();
+            self.track_pos = Some(self.track_time(sink_pos));
+            self.track_streamed = false;
+            self.refresh_gain();
+            self.resume_saved_position();
+            return;
+        }
+
+        if !self.gapless || self.crossfade || self.status != Status::Playing || sink_len != 1 {
+            return;
+        }
+
+        let (Some(dur), Some(pos)) = (self.track_duration, self.track_pos) else {
+            return;
+        };
+        if dur.saturating_sub(pos) > GAPLESS_PRELOAD {
+            return;
+        }
+
+        let Some((next_track, from_queue)) = self.upcoming_track() else {
+            return;
+        };
+
+        match player::append_track(&self.sink, &next_track, &self.effects) {
+            Ok(()) => {
+                if from_queue {
+                    self.take_next_from_queue();
+                }
+                self.preload = Some(Preload {
+                    path: next_track,
+                    from_queue,
+                });
+            }
+            Err(e) => self.display_info(e.to_string().as_str()),
+        }
+    }
+
+    fn update_crossfade(&mut self) {
+        // Ramp the outgoing sink down and the incoming one up
+        // according to how far the incoming track has played.
+        if let Some(fading_sink) = &self.fading_sink {
+            let elapsed = self.sink.lock().unwrap().get_pos();
+            let progress = (elapsed.as_secs_f32() / self.crossfade_dur.as_secs_f32()).min(1.0);
+
+            let fading_done = {
+                let fading_sink = fading_sink.lock().unwrap();
+                fading_sink.set_volume(self.volume * self.fading_gain * (1.0 - progress));
+                progress >= 1.0 || fading_sink.empty()
+            };
+
+            if fading_done {
+                self.finish_crossfade();
+            } else {
+                let volume = self.volume * self.gain_factor() * progress;
+                self.sink.lock().unwrap().set_volume(volume);
+            }
+            return;
+        }
+
+        // An A-B loop keeps the track going
+        if !self.crossfade
+            || self.status != Status::Playing
+            || matches!(self.ab_repeat, AbRepeat::Loop(..))
+        {
+            return;
+        }
+
+        let (Some(dur), Some(pos)) = (self.track_duration, self.track_pos) else {
+            return;
+        };
+        // Tracks too short to hold a fade out and a fade in are left to end normally.
+        // The fade runs in time played, which the speed stretches or shortens.
+        let speed = self.tempo().speed;
+        if dur.div_f32(speed) < self.crossfade_dur * 2
+            || dur.saturating_sub(pos).div_f32(speed) > self.crossfade_dur
+        {
+            return;
+        }
+
+        // A track with a resume point starts after this one ends instead, at its saved position
+        let Some((next_track, from_queue)) = self.upcoming_track() else {
+            return;
+        };
+        if self.resume_position(&next_track).is_some() {
+            return;
+        }
+
+        let incoming_sink = player::new_sink(&self.stream);
+        incoming_sink.set_volume(0.0);
+        let incoming_sink = Arc::new(Mutex::new(incoming_sink));
+
+        if let Err(e) = player::load_track(&incoming_sink, &next_track, &self.effects, true) {
+            self.display_info(e.to_string().as_str());
+            return;
+        }
+
+        if from_queue {
+            self.take_next_from_queue();
+        }
+        self.fading_sink = Some(std::mem::replace(&mut self.sink, incoming_sink));
+        self.fading_gain = self.gain_factor();
+        self.track_duration = player::get_track_duration(&next_track).ok();
+        self.requeue_finished();
+        self.push_history();
+        self.play_counted = false;
+        self.track_path = Some(next_track);
+        self.ab_repeat = AbRepeat::Off;
+        self.track_pos = Some(Duration::ZERO);
+        self.speed_anchor = None;
+        self.track_streamed = false;
+        self.refresh_gain();
+    }
+
+    // The track that should follow the current one without a gap,
+    // and whether it has to be taken from the queue.
+    fn upcoming_track(&self) -> Option<(PathBuf, bool)> {
+        if self.repeat == RepeatMode::One {
+            return self.track_path.clone().map(|path| (path, false));
+        }
+
+        let next = self
+            .next_queue_index()
+            .and_then(|idx| self.track_queue.get(idx));
+        match next {
+            Some(path) if player::is_playable_now(path) => Some((path.clone(), true)),
+            _ => None,
+        }
+    }
+
+    // Called when playback moves on from the current track without the sink running empty.
+    fn requeue_finished(&mut self) {
+        if self.repeat == RepeatMode::Queue
+            && let Some(path) = self.track_path.clone()
+        {
+            self.track_queue.push_back(path);
+        }
+    }
+
+    // Stops the outgoing track of a crossfade and restores the full volume of the current one.
+    fn finish_crossfade(&mut self) {
+        if self.fading_sink.take().is_some() {
+            self.apply_volume();
+        }
+    }
+
+    // Reads the gain tags of the current track. Only natively decoded tracks use them,
+    // converted and streamed ones are already normalized by ffmpeg.
+    fn refresh_gain(&mut self) {
+        self.gain_tags = match &self.track_path {
+            Some(path)
+                if !self.track_streamed && player::is_rodio_supported(path).unwrap_or(false) =>
+            {
+                Some(GainTags::read(path))
+            }
+            _ => None,
+        };
+        self.apply_volume();
+    }
+
+    // Scans on a worker thread, progress messages come back over the channel.
+    fn start_scan(&mut self, dir: PathBuf) {
+        self.scan = Some(Scan::start(vec![dir]));
+    }
+
+    fn update_scan(&mut self) {
+        let Some(scan) = &self.scan else {
+            return;
+        };
+
+        let mut messages = Vec::new();
+        let finished = loop {
+            match scan.messages.try_recv() {
+                Ok(message) => messages.push(message),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+        for message in messages {
+            self.display_info(&message);
+        }
+
+        if finished {
+            self.scan = None;
+            // The current track may have just been tagged
+            self.refresh_gain();
+            self.display_info("Loudness scan finished");
+        }
+    }
+
+    // Rescans on a worker thread from what is already indexed, so only new and changed files
+    // have their tags read. The index is saved before the result comes back.
+    fn start_library_rescan(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        let index = self.library.clone();
+        thread::spawn(move || {
+            let rescan = library::rescan(&index);
+            let _ = library::save_index(&rescan.tracks);
+            let _ = sender.send(rescan);
+        });
+        self.library_rescan = Some(receiver);
+    }
+
+    fn update_library_rescan(&mut self) {
+        let Some(receiver) = &self.library_rescan else {
+            return;
+        };
+
+        match receiver.try_recv() {
+            Ok(rescan) => {
+                self.library_rescan = None;
+                // Nothing is shown when nothing changed, as on most startups
+                if rescan.read > 0 || rescan.removed > 0 {
+                    self.display_info(
+                        format!(
+                            "Library: {} tracks, {} new or changed, {} removed",
+                            rescan.tracks.len(),
+                            rescan.read,
+                            rescan.removed
+                        )
+                        .as_str(),
+                    );
+                }
+                // A missing folder is only worth mentioning once something has been indexed
+                if !rescan.tracks.is_empty() {
+                    for dir in &rescan.unavailable {
+                        self.display_info(
+                            format!("Library: cannot read {}, keeping its tracks", dir.display())
+                                .as_str(),
+                        );
+                    }
+                }
+                self.library = rescan.tracks;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.library_rescan = None,
+        }
+    }
+
+    // The ReplayGain adjustment applied to the current track, in dB.
+    pub fn gain_db(&self) -> Option<f32> {
+        self.gain_tags?.gain_db(self.gain_mode, self.preamp)
+    }
+
+    fn gain_factor(&self) -> f32 {
+        self.gain_db().map(gain::db_to_factor).unwrap_or(1.0)
+    }
+
+    // Sets the sink to the user's volume with the track's gain on top.
+    // During a crossfade the volumes are ramped in update_crossfade instead.
+    fn apply_volume(&self) {
+        if self.fading_sink.is_none() {
+            let volume = self.volume * self.gain_factor();
+            self.sink.lock().unwrap().set_volume(volume);
+        }
+    }
+
+    // Forgets the preloaded track before the sink gets cleared,
+    // returning it to the front of the queue if it was taken from there.
+    fn cancel_preload(&mut self) {
+        if let Some(preload) = self.preload.take()
+            && preload.from_queue
+        {
+            self.return_to_queue(preload.path);
+        }
     }
 
     fn display_info(&mut self, info: &str) {
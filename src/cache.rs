@@ -0,0 +1,98 @@
+use color_eyre::eyre::Result;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::dirs;
+
+// Converted tracks beyond this total size get evicted, least recently played first.
+const CACHE_SIZE_CAP: u64 = 2 * 1024 * 1024 * 1024;
+const CACHE_EXTENSION: &str = "flac";
+
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(dirs::cache_dir()?.join("converted"))
+}
+
+// Where the converted version of a track lives. The name is derived from the track's
+// path, modification time and size, so editing the source invalidates the entry.
+pub fn cached_path(track: &Path) -> Result<PathBuf> {
+    let metadata = fs::metadata(track)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let source = fs::canonicalize(track).unwrap_or_else(|_| track.to_path_buf());
+
+    let mut key = source.as_os_str().as_encoded_bytes().to_vec();
+    key.extend_from_slice(&modified.as_nanos().to_le_bytes());
+    key.extend_from_slice(&metadata.len().to_le_bytes());
+
+    Ok(cache_dir()?.join(format!("{:016x}.{}", fnv1a(&key), CACHE_EXTENSION)))
+}
+
+// Path ffmpeg writes to while converting, renamed to the cached path once it succeeds
+// so a half written file is never mistaken for a finished one.
+pub fn partial_path(cached: &Path) -> PathBuf {
+    cached.with_extension(format!("part.{}", CACHE_EXTENSION))
+}
+
+pub fn is_cached(track: &Path) -> bool {
+    cached_path(track).is_ok_and(|path| path.is_file())
+}
+
+// Marks a cached file as recently used, eviction goes by modification time.
+pub fn touch(cached: &Path) {
+    if let Ok(file) = File::options().append(true).open(cached) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+// Removes the least recently used entries until the cache fits under its size cap.
+// Files in `keep` are never removed, even if that leaves the cache over the cap.
+pub fn evict(keep: &[PathBuf]) -> Result<()> {
+    let mut entries: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+    for entry in fs::read_dir(cache_dir()?)?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(CACHE_EXTENSION)
+            || path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| stem.ends_with(".part"))
+        {
+            continue;
+        }
+        if let Ok(metadata) = entry.metadata() {
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            entries.push((path, metadata.len(), modified));
+        }
+    }
+
+    let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+    entries.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, size, _) in entries {
+        if total <= CACHE_SIZE_CAP {
+            break;
+        }
+        if keep.contains(&path) {
+            continue;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= size;
+        }
+    }
+
+    Ok(())
+}
+
+// 64-bit FNV-1a, stable across runs and Rust versions unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
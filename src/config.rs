@@ -0,0 +1,25 @@
+use std::fs;
+
+use crate::dirs;
+
+const CONFIG_FILE: &str = "config";
+
+// Every value given for `key` in the config file, which holds one `key = value` per line.
+// Lines starting with # are comments.
+pub fn values(key: &str) -> Vec<String> {
+    let contents = dirs::config_dir()
+        .and_then(|dir| Ok(fs::read_to_string(dir.join(CONFIG_FILE))?))
+        .unwrap_or_default();
+
+    contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim().to_string())
+        .collect()
+}
+
+pub fn value(key: &str) -> Option<String> {
+    values(key).into_iter().next()
+}
@@ -0,0 +1,190 @@
+use color_eyre::eyre::{Result, eyre};
+use lofty::{file::AudioFile, probe::Probe};
+use rust_ffmpeg::prelude::*;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    runtime::Runtime,
+};
+
+use crate::cache;
+
+// How often the worker checks whether the conversion has been cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, PartialEq)]
+pub enum ConversionState {
+    // Holds how much of the source has been processed so far.
+    Running(Duration),
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+// Where a queued track stands regarding conversion, shown next to it in the queue.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConversionMark {
+    // Decoded by rodio directly, nothing to convert.
+    Native,
+    Pending,
+    Ready,
+    Failed,
+}
+
+// A format conversion running on its own thread, so the event loop keeps going while ffmpeg works.
+pub struct Conversion {
+    pub track: PathBuf,
+    pub output: PathBuf,
+    pub total: Option<Duration>,
+    state: Arc<Mutex<ConversionState>>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Conversion {
+    pub fn start(track: PathBuf, output: PathBuf) -> Self {
+        let total = Probe::open(&track)
+            .and_then(|probe| probe.read())
+            .map(|tagged_file| tagged_file.properties().duration())
+            .ok()
+            .filter(|dur| !dur.is_zero());
+
+        let state = Arc::new(Mutex::new(ConversionState::Running(Duration::ZERO)));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let (track, output) = (track.clone(), output.clone());
+            let (state, cancel) = (Arc::clone(&state), Arc::clone(&cancel));
+            thread::spawn(move || {
+                // ffmpeg writes next to the final path and the result is moved into place
+                // only once complete, so other tracks never see a partial file.
+                let partial = cache::partial_path(&output);
+                let finished = run_ffmpeg(track, partial.clone(), &state, &cancel);
+                let result = match finished.and_then(|finished| {
+                    if finished {
+                        fs::rename(&partial, &output)?;
+                    }
+                    Ok(finished)
+                }) {
+                    Ok(true) => ConversionState::Finished,
+                    Ok(false) => ConversionState::Cancelled,
+                    Err(e) => ConversionState::Failed(e.to_string()),
+                };
+                if result != ConversionState::Finished {
+                    let _ = fs::remove_file(&partial);
+                }
+                *state.lock().unwrap() = result;
+            })
+        };
+
+        Self {
+            track,
+            output,
+            total,
+            state,
+            cancel,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn state(&self) -> ConversionState {
+        self.state.lock().unwrap().clone()
+    }
+
+    // Fraction of the source processed, if its duration is known.
+    pub fn progress(&self) -> Option<f32> {
+        let total = self.total?;
+        match self.state() {
+            ConversionState::Running(processed) => {
+                Some((processed.as_secs_f32() / total.as_secs_f32()).min(1.0))
+            }
+            ConversionState::Finished => Some(1.0),
+            _ => None,
+        }
+    }
+
+    // Stops ffmpeg and waits for the worker to exit,
+    // so nothing writes to the output file once this returns.
+    pub fn cancel(mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Returns Ok(false) if the conversion got cancelled before ffmpeg finished.
+fn run_ffmpeg(
+    track: PathBuf,
+    output: PathBuf,
+    state: &Mutex<ConversionState>,
+    cancel: &AtomicBool,
+) -> Result<bool> {
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        let mut process = FFmpegBuilder::new()?
+            .input_path(track)
+            .output_path(output)
+            .overwrite()
+            .log_level(LogLevel::Error)
+            .audio_filter(AudioFilter::loudnorm())
+            .raw_args(["-progress", "pipe:1", "-nostats"])
+            .spawn()
+            .await?;
+
+        let stdout = process
+            .stdout()
+            .ok_or_else(|| eyre!("ffmpeg progress output unavailable"))?;
+        let mut lines = BufReader::new(stdout).lines();
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => {
+                        if let Some(processed) = parse_progress_line(&line) {
+                            *state.lock().unwrap() = ConversionState::Running(processed);
+                        }
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep(CANCEL_POLL) => {
+                    if cancel.load(Ordering::SeqCst) {
+                        process.kill().await?;
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        process.wait().await?;
+
+        Ok(true)
+    })
+}
+
+// ffmpeg's -progress output is a stream of key=value lines,
+// out_time_us holds the position reached in the source.
+fn parse_progress_line(line: &str) -> Option<Duration> {
+    let (key, value) = line.split_once('=')?;
+    match key.trim() {
+        "out_time_us" | "out_time_ms" => {
+            let micros: u64 = value.trim().parse().ok()?;
+            Some(Duration::from_micros(micros))
+        }
+        _ => None,
+    }
+}
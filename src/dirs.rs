@@ -0,0 +1,31 @@
+use color_eyre::eyre::{Result, eyre};
+use std::{env, path::PathBuf};
+
+// The XDG base directory in `var`, or `fallback` under the home directory when it is unset.
+fn xdg_dir(var: &str, fallback: &str) -> Result<PathBuf> {
+    let base = match env::var_os(var).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(fallback),
+            None => return Err(eyre!("no home directory available")),
+        },
+    };
+
+    Ok(base.join("firefly"))
+}
+
+pub fn cache_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn state_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
@@ -0,0 +1,300 @@
+use color_eyre::eyre::Result;
+use rodio::{Source, source::SeekError};
+use std::{
+    f64::consts::PI,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::dirs;
+
+pub const BAND_COUNT: usize = 10;
+// Octave spaced center frequencies, as on a classic graphic equalizer.
+pub const BAND_FREQUENCIES: [f32; BAND_COUNT] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+pub const GAIN_LIMIT: f32 = 12.0;
+pub const GAIN_STEP: f32 = 1.0;
+
+// About one octave wide per band.
+const BAND_Q: f64 = 1.41;
+// How many samples go by between checks for new band gains.
+const REFRESH_INTERVAL: usize = 1024;
+const PRESETS_FILE: &str = "eq_presets";
+
+// Band gains in dB, shared between the player and every source playing through the equalizer,
+// so adjusting a band is heard right away.
+pub type EqGains = Arc<Mutex<[f32; BAND_COUNT]>>;
+
+#[derive(Clone)]
+pub struct Preset {
+    pub name: String,
+    pub gains: [f32; BAND_COUNT],
+    pub builtin: bool,
+}
+
+impl Preset {
+    fn builtin(name: &str, gains: [f32; BAND_COUNT]) -> Self {
+        Self {
+            name: name.to_string(),
+            gains,
+            builtin: true,
+        }
+    }
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    vec![
+        Preset::builtin("Flat", [0.0; BAND_COUNT]),
+        Preset::builtin(
+            "Bass Boost",
+            [6.0, 5.0, 4.0, 2.5, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        ),
+        Preset::builtin(
+            "Vocal",
+            [-2.0, -2.0, -1.0, 0.0, 2.0, 3.5, 3.5, 2.0, 0.0, -1.0],
+        ),
+        Preset::builtin(
+            "Loudness",
+            [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 1.5, 3.0, 4.0],
+        ),
+    ]
+}
+
+fn presets_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()?.join(PRESETS_FILE))
+}
+
+// User presets are stored one per line as `name = gain gain ...`, lines that don't parse are skipped.
+pub fn load_user_presets() -> Vec<Preset> {
+    let Ok(contents) = presets_path().and_then(|path| Ok(fs::read_to_string(path)?)) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, gains) = line.rsplit_once('=')?;
+            let gains: Vec<f32> = gains
+                .split_whitespace()
+                .map(|gain| gain.parse().ok())
+                .collect::<Option<_>>()?;
+            Some(Preset {
+                name: name.trim().to_string(),
+                gains: gains.try_into().ok()?,
+                builtin: false,
+            })
+        })
+        .collect()
+}
+
+pub fn save_user_presets(presets: &[Preset]) -> Result<()> {
+    let path = presets_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let contents: String = presets
+        .iter()
+        .filter(|preset| !preset.builtin)
+        .map(|preset| {
+            let gains: Vec<String> = preset.gains.iter().map(|gain| gain.to_string()).collect();
+            format!("{} = {}\n", preset.name, gains.join(" "))
+        })
+        .collect();
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+// A second order IIR filter section, coefficients normalized so that a0 is 1.
+#[derive(Clone)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self { b, a, z: [0.0; 2] }
+    }
+
+    fn identity() -> Self {
+        Self::new([1.0, 0.0, 0.0], [1.0, 0.0, 0.0])
+    }
+
+    // Boosts or cuts around `freq`, from the Audio EQ Cookbook.
+    fn peaking(freq: f64, gain_db: f64, q: f64, sample_rate: f64) -> Self {
+        // Bands at or past the Nyquist frequency can't be represented
+        if gain_db == 0.0 || freq >= sample_rate * 0.45 {
+            return Self::identity();
+        }
+
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha / a;
+
+        Self::new(
+            [
+                (1.0 + alpha * a) / a0,
+                -2.0 * cos / a0,
+                (1.0 - alpha * a) / a0,
+            ],
+            [1.0, -2.0 * cos / a0, (1.0 - alpha / a) / a0],
+        )
+    }
+
+    // Swaps in new coefficients but keeps the filter's state, so the change doesn't click.
+    fn retune(&mut self, other: Biquad) {
+        self.b = other.b;
+        self.a = other.a;
+    }
+
+    fn reset(&mut self) {
+        self.z = [0.0; 2];
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+// Runs a source through a bank of peaking filters, one per band and channel.
+// Sits between the decoder and the sink, so it applies the same way to native,
+// converted and streamed tracks.
+pub struct Equalizer<S> {
+    source: S,
+    gains: EqGains,
+    applied: [f32; BAND_COUNT],
+    filters: Vec<Vec<Biquad>>,
+    sample_rate: u32,
+    channel: usize,
+    until_refresh: usize,
+    // False while every band is flat, samples then pass through untouched.
+    active: bool,
+}
+
+impl<S: Source> Equalizer<S> {
+    pub fn new(source: S, gains: EqGains) -> Self {
+        let mut equalizer = Self {
+            source,
+            gains,
+            applied: [0.0; BAND_COUNT],
+            filters: Vec::new(),
+            sample_rate: 0,
+            channel: 0,
+            until_refresh: 0,
+            active: false,
+        };
+        equalizer.refresh();
+
+        equalizer
+    }
+
+    // Picks up changed band gains, or a changed layout from the source.
+    fn refresh(&mut self) {
+        let gains = *self.gains.lock().unwrap();
+        let channels = self.source.channels().max(1) as usize;
+        let sample_rate = self.source.sample_rate();
+
+        let layout_changed = channels != self.filters.len() || sample_rate != self.sample_rate;
+        if !layout_changed && gains == self.applied {
+            return;
+        }
+
+        let bank: Vec<Biquad> = BAND_FREQUENCIES
+            .iter()
+            .zip(gains)
+            .map(|(freq, gain)| {
+                Biquad::peaking(*freq as f64, gain as f64, BAND_Q, sample_rate as f64)
+            })
+            .collect();
+
+        if layout_changed {
+            self.filters = vec![bank; channels];
+            self.channel = 0;
+        } else {
+            for filters in &mut self.filters {
+                for (filter, tuned) in filters.iter_mut().zip(bank.iter().cloned()) {
+                    filter.retune(tuned);
+                }
+            }
+        }
+
+        let active = gains.iter().any(|gain| *gain != 0.0);
+        if active && !self.active {
+            self.reset();
+        }
+
+        self.active = active;
+        self.applied = gains;
+        self.sample_rate = sample_rate;
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+    }
+}
+
+impl<S: Source> Iterator for Equalizer<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Only refresh on frame boundaries, so samples stay matched to their channel
+        if self.channel == 0 && self.until_refresh == 0 {
+            self.refresh();
+            self.until_refresh = REFRESH_INTERVAL;
+        }
+        self.until_refresh = self.until_refresh.saturating_sub(1);
+
+        let sample = self.source.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.filters.len();
+
+        if !self.active {
+            return Some(sample);
+        }
+
+        let output = self.filters[channel]
+            .iter_mut()
+            .fold(sample as f64, |x, filter| filter.process(x));
+
+        Some(output as f32)
+    }
+}
+
+impl<S: Source> Source for Equalizer<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.source.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.reset();
+        self.channel = 0;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use lofty::{
+    file::TaggedFileExt,
+    probe::Probe,
+    tag::{ItemKey, Tag},
+};
+use std::path::Path;
+
+// Preamp is kept within this many decibels either way.
+pub const PREAMP_LIMIT: f32 = 15.0;
+
+// R128 gains are relative to -23 LUFS, ReplayGain ones to -18 LUFS.
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GainMode {
+    Off,
+    Track,
+    Album,
+}
+
+impl GainMode {
+    pub fn next(self) -> Self {
+        match self {
+            GainMode::Off => GainMode::Track,
+            GainMode::Track => GainMode::Album,
+            GainMode::Album => GainMode::Off,
+        }
+    }
+}
+
+// Loudness information read from a track's tags, gains in dB and peaks as linear amplitude.
+#[derive(Clone, Copy, Default)]
+pub struct GainTags {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl GainTags {
+    pub fn read(path: &Path) -> Self {
+        let Ok(tagged_file) = Probe::open(path).and_then(|probe| probe.read()) else {
+            return Self::default();
+        };
+
+        let mut gain_tags = Self::default();
+        for tag in tagged_file.tags() {
+            gain_tags.track_gain = gain_tags
+                .track_gain
+                .or_else(|| read_db(tag, ItemKey::ReplayGainTrackGain))
+                .or_else(|| read_r128(tag, "R128_TRACK_GAIN"));
+            gain_tags.album_gain = gain_tags
+                .album_gain
+                .or_else(|| read_db(tag, ItemKey::ReplayGainAlbumGain))
+                .or_else(|| read_r128(tag, "R128_ALBUM_GAIN"));
+            gain_tags.track_peak = gain_tags
+                .track_peak
+                .or_else(|| read_number(tag, ItemKey::ReplayGainTrackPeak));
+            gain_tags.album_peak = gain_tags
+                .album_peak
+                .or_else(|| read_number(tag, ItemKey::ReplayGainAlbumPeak));
+        }
+
+        gain_tags
+    }
+
+    // The gain in dB to apply in the given mode, with the preamp added.
+    // Album mode falls back to the track gain for tracks without album tags.
+    // None when the mode is off or the track carries no gain tags.
+    pub fn gain_db(&self, mode: GainMode, preamp: f32) -> Option<f32> {
+        let (gain, peak) = match mode {
+            GainMode::Off => return None,
+            GainMode::Track => (self.track_gain?, self.track_peak),
+            GainMode::Album => match self.album_gain {
+                Some(gain) => (gain, self.album_peak.or(self.track_peak)),
+                None => (self.track_gain?, self.track_peak),
+            },
+        };
+
+        let mut gain = gain + preamp;
+        // Don't boost the track's peak past full scale
+        if let Some(peak) = peak.filter(|peak| *peak > 0.0) {
+            gain = gain.min(-20.0 * peak.log10());
+        }
+
+        Some(gain)
+    }
+}
+
+pub fn db_to_factor(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// Parses values such as "-6.54 dB".
+fn read_db(tag: &Tag, key: ItemKey) -> Option<f32> {
+    let value = tag.get_string(&key)?.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok()
+}
+
+fn read_number(tag: &Tag, key: ItemKey) -> Option<f32> {
+    tag.get_string(&key)?.trim().parse().ok()
+}
+
+// R128 gains are stored as Q7.8 fixed point integers.
+fn read_r128(tag: &Tag, key: &str) -> Option<f32> {
+    let value: i32 = tag
+        .get_string(&ItemKey::Unknown(key.to_string()))?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(value as f32 / 256.0 + R128_TO_REPLAYGAIN_DB)
+}
@@ -0,0 +1,387 @@
+use color_eyre::eyre::Result;
+use lofty::{
+    file::{AudioFile, TaggedFileExt},
+    probe::Probe,
+    tag::{Accessor, ItemKey, ItemValue, Tag},
+};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{config, dirs, player};
+
+const STATS_FILE: &str = "play_stats";
+const INDEX_FILE: &str = "library";
+// The first line of the index, a different one means the index is rebuilt from scratch.
+const INDEX_HEADER: &str = "# firefly library index v1";
+
+// What is known about a track in the library, read from its tags.
+#[derive(Clone, Default)]
+pub struct TrackInfo {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    // From 0 to 5 stars.
+    pub rating: Option<f32>,
+    pub duration: Option<Duration>,
+    // When the track first made it into the index, taken from the file's modification time
+    // at that point.
+    pub added: Option<SystemTime>,
+    // The file's modification time and size when its tags were read, a rescan only
+    // reads the tags again when either has changed.
+    pub modified: Option<SystemTime>,
+    pub size: u64,
+}
+
+// The outcome of a rescan, with how many files had their tags read and how many left the library.
+pub struct Rescan {
+    pub tracks: Vec<TrackInfo>,
+    pub read: usize,
+    pub removed: usize,
+    // Directories that couldn't be read, like a library on an unmounted drive.
+    // The tracks indexed under them are kept as they were.
+    pub unavailable: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+pub struct PlayStats {
+    pub last_played: SystemTime,
+    pub plays: u32,
+}
+
+// Directories making up the library, from `library = <dir>` lines in the config file,
+// or ~/Music when there are none.
+pub fn library_dirs() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = config::values("library")
+        .into_iter()
+        .map(|dir| expand_home(&dir))
+        .collect();
+    if !dirs.is_empty() {
+        return dirs;
+    }
+
+    env::var_os("HOME")
+        .map(|home| vec![PathBuf::from(home).join("Music")])
+        .unwrap_or_default()
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
+
+// Walks the library directories, reading tags only from files that are new or have changed
+// since `index` was built. The result is sorted by path.
+pub fn rescan(index: &[TrackInfo]) -> Rescan {
+    let mut known: HashMap<&Path, &TrackInfo> = index
+        .iter()
+        .map(|track| (track.path.as_path(), track))
+        .collect();
+
+    let mut files = Vec::new();
+    let mut unavailable = Vec::new();
+    for dir in library_dirs() {
+        collect_audio_files(&dir, &mut files, &mut unavailable);
+    }
+    files.sort();
+    files.dedup();
+
+    let mut read = 0;
+    let mut tracks: Vec<TrackInfo> = files
+        .iter()
+        .map(|path| {
+            let meta = fs::metadata(path).ok();
+            let modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+            let size = meta.as_ref().map_or(0, |meta| meta.len());
+
+            match known.remove(path.as_path()) {
+                Some(track) if track.modified == modified && track.size == size => track.clone(),
+                previous => {
+                    read += 1;
+                    let mut track = read_track(path);
+                    // Changed files keep the date they first came in
+                    if let Some(previous) = previous {
+                        track.added = previous.added;
+                    }
+                    track
+                }
+            }
+        })
+        .collect();
+
+    // Tracks that weren't found only leave the library if their directory was actually read,
+    // otherwise they would lose their added dates whenever a drive is missing
+    let (kept, removed): (Vec<&TrackInfo>, Vec<&TrackInfo>) = known
+        .into_values()
+        .partition(|track| unavailable.iter().any(|dir| track.path.starts_with(dir)));
+    if !kept.is_empty() {
+        tracks.extend(kept.into_iter().cloned());
+        tracks.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    Rescan {
+        tracks,
+        read,
+        removed: removed.len(),
+        unavailable,
+    }
+}
+
+// Hidden directories are skipped, symlinked ones aren't followed so links can't loop.
+// Directories that can't be read are added to `unavailable`.
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>, unavailable: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        unavailable.push(dir.to_path_buf());
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() && !hidden => {
+                collect_audio_files(&path, files, unavailable)
+            }
+            Ok(file_type) if !file_type.is_dir() && player::is_audio_file(&path) => {
+                files.push(path)
+            }
+            _ => {}
+        }
+    }
+}
+
+// Files without tags are still listed, with only what the file system knows about them.
+pub fn read_track(path: &Path) -> TrackInfo {
+    let meta = fs::metadata(path).ok();
+    let modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+    let mut info = TrackInfo {
+        path: path.to_path_buf(),
+        added: modified,
+        modified,
+        size: meta.map_or(0, |meta| meta.len()),
+        ..Default::default()
+    };
+    let Ok(tagged_file) = Probe::open(path).and_then(|probe| probe.read()) else {
+        return info;
+    };
+
+    info.duration = Some(tagged_file.properties().duration()).filter(|dur| !dur.is_zero());
+    for tag in tagged_file.tags() {
+        info.title = info.title.or_else(|| tag.title().map(String::from));
+        info.artist = info.artist.or_else(|| tag.artist().map(String::from));
+        info.album = info.album.or_else(|| tag.album().map(String::from));
+        info.track_number = info.track_number.or_else(|| tag.track());
+        info.genre = info.genre.or_else(|| tag.genre().map(String::from));
+        info.year = info.year.or_else(|| tag.year());
+        info.rating = info.rating.or_else(|| read_rating(tag));
+    }
+
+    info
+}
+
+// Text ratings come on different scales depending on the program that wrote them:
+// percentages, tenths or plain stars, and now and then popularimeter bytes up to 255.
+fn read_rating(tag: &Tag) -> Option<f32> {
+    let value = match tag.get(&ItemKey::Popularimeter)?.value() {
+        ItemValue::Text(text) => text.trim().parse::<f32>().ok()?,
+        // A popularimeter frame: an email address, a NUL, the rating byte, then a play counter
+        ItemValue::Binary(bytes) => {
+            let nul = bytes.iter().position(|byte| *byte == 0)?;
+            return popularimeter_stars(*bytes.get(nul + 1)?);
+        }
+        _ => return None,
+    };
+
+    let stars = match value {
+        v if v <= 5.0 => v,
+        v if v <= 10.0 => v / 2.0,
+        v if v <= 100.0 => v / 20.0,
+        v => v / 51.0,
+    };
+    Some(stars.clamp(0.0, 5.0))
+}
+
+// Players write 1, 64, 128, 196 and 255 for one to five stars and 0 for unrated,
+// bytes in between are read by the ranges most players use.
+fn popularimeter_stars(byte: u8) -> Option<f32> {
+    match byte {
+        0 => None,
+        1..=31 => Some(1.0),
+        32..=95 => Some(2.0),
+        96..=159 => Some(3.0),
+        160..=223 => Some(4.0),
+        224..=255 => Some(5.0),
+    }
+}
+
+fn index_path() -> Result<PathBuf> {
+    Ok(dirs::data_dir()?.join(INDEX_FILE))
+}
+
+// The index holds one track per line, its fields separated by tabs:
+// path, modified, size, added, duration, track number, year, rating, title, artist, album, genre.
+// Times are in nanoseconds since the epoch, the duration in milliseconds and
+// fields a track doesn't have are left empty.
+pub fn load_index() -> Vec<TrackInfo> {
+    let Ok(contents) = index_path().and_then(|path| Ok(fs::read_to_string(path)?)) else {
+        return Vec::new();
+    };
+    let mut lines = contents.lines();
+    if lines.next() != Some(INDEX_HEADER) {
+        return Vec::new();
+    }
+
+    lines.filter_map(parse_index_line).collect()
+}
+
+fn parse_index_line(line: &str) -> Option<TrackInfo> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [
+        path,
+        modified,
+        size,
+        added,
+        duration,
+        track_number,
+        year,
+        rating,
+        title,
+        artist,
+        album,
+        genre,
+    ] = fields[..]
+    else {
+        return None;
+    };
+
+    let text = |field: &str| Some(field.to_string()).filter(|field| !field.is_empty());
+    let time = |field: &str| {
+        let nanos: u64 = field.parse().ok()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos))
+    };
+
+    Some(TrackInfo {
+        path: PathBuf::from(path),
+        title: text(title),
+        artist: text(artist),
+        album: text(album),
+        track_number: track_number.parse().ok(),
+        genre: text(genre),
+        year: year.parse().ok(),
+        rating: rating.parse().ok(),
+        duration: duration.parse().ok().map(Duration::from_millis),
+        added: time(added),
+        modified: time(modified),
+        size: size.parse().ok()?,
+    })
+}
+
+pub fn save_index(tracks: &[TrackInfo]) -> Result<()> {
+    let path = index_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Tabs and line breaks in tags would break up the line
+    let text = |field: &Option<String>| {
+        field
+            .as_deref()
+            .unwrap_or_default()
+            .replace(['\t', '\n', '\r'], " ")
+    };
+    let number = |field: Option<String>| field.unwrap_or_default();
+    let time = |field: Option<SystemTime>| {
+        number(
+            field
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since| since.as_nanos().to_string()),
+        )
+    };
+
+    let mut contents = format!("{INDEX_HEADER}\n");
+    for track in tracks {
+        // Paths that aren't valid UTF-8 are read again on every rescan instead
+        let Some(track_path) = track.path.to_str() else {
+            continue;
+        };
+        let fields = [
+            track_path.to_string(),
+            time(track.modified),
+            track.size.to_string(),
+            time(track.added),
+            number(track.duration.map(|dur| dur.as_millis().to_string())),
+            number(track.track_number.map(|n| n.to_string())),
+            number(track.year.map(|year| year.to_string())),
+            number(track.rating.map(|rating| rating.to_string())),
+            text(&track.title),
+            text(&track.artist),
+            text(&track.album),
+            text(&track.genre),
+        ];
+        contents.push_str(&fields.join("\t"));
+        contents.push('\n');
+    }
+
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(temp, path)?;
+
+    Ok(())
+}
+
+fn stats_path() -> Result<PathBuf> {
+    Ok(dirs::state_dir()?.join(STATS_FILE))
+}
+
+// Stored one track per line as `last_played plays path`, the time in seconds since the epoch.
+pub fn load_stats() -> HashMap<PathBuf, PlayStats> {
+    let Ok(contents) = stats_path().and_then(|path| Ok(fs::read_to_string(path)?)) else {
+        return HashMap::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let secs: u64 = fields.next()?.parse().ok()?;
+            let plays = fields.next()?.parse().ok()?;
+            let path = PathBuf::from(fields.next()?);
+            let last_played = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            Some((path, PlayStats { last_played, plays }))
+        })
+        .collect()
+}
+
+pub fn save_stats(stats: &HashMap<PathBuf, PlayStats>) -> Result<()> {
+    let path = stats_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let contents: String = stats
+        .iter()
+        .filter_map(|(track, stats)| {
+            let secs = stats
+                .last_played
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?
+                .as_secs();
+            Some(format!("{secs} {} {}\n", stats.plays, track.to_str()?))
+        })
+        .collect();
+
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(temp, path)?;
+
+    Ok(())
+}
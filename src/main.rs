@@ -1,22 +1,63 @@
-use std::path::Path;
-
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, eyre};
+use std::{path::PathBuf, sync::atomic::AtomicBool};
 
 pub mod app;
+pub mod cache;
+pub mod config;
+pub mod convert;
+pub mod dirs;
+pub mod eq;
+pub mod gain;
+pub mod library;
 pub mod player;
+pub mod playlist;
+pub mod resume;
+pub mod scan;
+pub mod session;
+pub mod smart;
+pub mod stream;
+pub mod tempo;
 pub mod ui;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let mut terminal = ratatui::init();
-    let result = app::App::new().run(&mut terminal);
-    ratatui::restore();
+    // `firefly scan <paths...>` tags files and directories without starting the player
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("scan") {
+        let paths: Vec<PathBuf> = args[1..].iter().map(PathBuf::from).collect();
+        if paths.is_empty() {
+            return Err(eyre!("usage: firefly scan <files or directories...>"));
+        }
+        scan::scan_paths(paths, &AtomicBool::new(false), &mut |message| {
+            println!("{message}")
+        });
+        return Ok(());
+    }
 
-    let temp_file = Path::new(player::CONVERTED_TRACK);
-    if temp_file.exists() {
-        std::fs::remove_file(temp_file).expect("Error removing temporary file.");
+    // `firefly library` brings the library index up to date without starting the player
+    if args.first().map(String::as_str) == Some("library") {
+        let rescan = library::rescan(&library::load_index());
+        library::save_index(&rescan.tracks)?;
+        for dir in &rescan.unavailable {
+            println!("Cannot read {}, its indexed tracks are kept", dir.display());
+        }
+        println!(
+            "{} tracks, {} new or changed, {} removed",
+            rescan.tracks.len(),
+            rescan.read,
+            rescan.removed
+        );
+        return Ok(());
     }
 
+    // Anything else given is queued, playlists included
+    let paths: Vec<PathBuf> = args.iter().map(PathBuf::from).collect();
+
+    let mut terminal = ratatui::init();
+    let mut app = app::App::new();
+    app.enqueue_paths(paths);
+    let result = app.run(&mut terminal);
+    ratatui::restore();
     result
 }
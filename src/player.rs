@@ -1,18 +1,27 @@
 use color_eyre::eyre::{Result, eyre};
-use lofty::{file::AudioFile, probe::Probe};
+use lofty::{
+    file::{AudioFile, FileType},
+    probe::Probe,
+};
 use rfd::FileDialog;
-use rodio::{Decoder, OutputStream, Sink};
-use rust_ffmpeg::prelude::*;
+use rodio::{Decoder, OutputStream, Sink, Source, source::SeekError};
 use std::{
     collections::VecDeque,
     fs::{self, File},
-    ops::{Add, Sub},
-    path::PathBuf,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
     sync::{Arc, Mutex},
-    thread,
     time::Duration,
 };
-use tokio::runtime::Runtime;
+
+use crate::{
+    cache,
+    eq::{EqGains, Equalizer},
+    playlist::{PLAYLIST_FORMATS, is_playlist},
+    stream::StreamSource,
+    tempo::{SharedTempo, TimeStretch},
+};
 
 #[derive(PartialEq)]
 pub enum Status {
@@ -23,38 +32,139 @@ pub enum Status {
 
 const RODIO_SUPPORTED_FORMATS: [&'static str; 4] = ["flac", "mp3", "ogg", "wav"];
 const TESTED_FORMATS: [&'static str; 6] = ["mp3", "flac", "wav", "ogg", "opus", "oga"];
-const UNTESTED_FORMATS: [&'static str; 5] = ["pcm", "aiff", "aac", "wma", "alac"];
-const AUDIO_FORMATS: [&'static str; 11] = [
-    "mp3", "flac", "wav", "ogg", "opus", "oga", "pcm", "aiff", "aac", "wma", "alac",
+const UNTESTED_FORMATS: [&'static str; 6] = ["pcm", "aiff", "aac", "m4a", "wma", "alac"];
+const AUDIO_FORMATS: [&'static str; 12] = [
+    "mp3", "flac", "wav", "ogg", "opus", "oga", "pcm", "aiff", "aac", "m4a", "wma", "alac",
+];
+// Files that come along with albums, skipped without reading them.
+const NON_AUDIO_FORMATS: [&'static str; 18] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff", "cue", "log", "nfo", "txt", "pdf",
+    "md5", "sfv", "ffp", "accurip", "db",
 ];
-pub const CONVERTED_TRACK: &'static str = "temp.flac";
+
+// Identifies the format by the file's header rather than its name.
+fn probe_file_type(path: &Path) -> Option<FileType> {
+    let file = File::open(path).ok()?;
+    Probe::new(BufReader::new(file))
+        .guess_file_type()
+        .ok()?
+        .file_type()
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
 
 pub fn is_rodio_supported(path: &PathBuf) -> Result<bool> {
-    if path.is_file() {
-        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
-            if RODIO_SUPPORTED_FORMATS.contains(&extension) {
-                Ok(true)
-            } else {
-                Ok(false)
-            }
-        } else {
-            Err(eyre!("file has no extension"))
+    if !path.is_file() {
+        return Err(eyre!("path is not a file"));
+    }
+
+    if let Some(file_type) = probe_file_type(path) {
+        return Ok(matches!(
+            file_type,
+            FileType::Flac | FileType::Mpeg | FileType::Vorbis | FileType::Wav
+        ));
+    }
+
+    // The header was not recognized, go by the extension and let the decoder have a try
+    match lowercase_extension(path) {
+        Some(extension) if RODIO_SUPPORTED_FORMATS.contains(&extension.as_str()) => Ok(true),
+        Some(extension) if AUDIO_FORMATS.contains(&extension.as_str()) => Ok(false),
+        _ => Err(eyre!(
+            "{} is not a recognized audio file",
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("[Invalid UTF-8 name]")
+        )),
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+
+    // Only files without an extension or with one that isn't known either way are probed
+    match lowercase_extension(path) {
+        Some(extension) if AUDIO_FORMATS.contains(&extension.as_str()) => true,
+        Some(extension)
+            if NON_AUDIO_FORMATS.contains(&extension.as_str())
+                || PLAYLIST_FORMATS.contains(&extension.as_str()) =>
+        {
+            false
         }
-    } else {
-        Err(eyre!("path is not a file"))
+        _ => probe_file_type(path).is_some(),
+    }
+}
+
+// The file rodio should decode for a track, which is its converted copy in the cache
+// when rodio cannot decode the original.
+pub fn playable_path(track: &PathBuf) -> Result<PathBuf> {
+    if is_rodio_supported(track)? {
+        return Ok(track.clone());
+    }
+
+    let cached = cache::cached_path(track)?;
+    if !cached.is_file() {
+        return Err(eyre!("track has not been converted yet"));
+    }
+    cache::touch(&cached);
+
+    Ok(cached)
+}
+
+// Whether a track can be handed to rodio without running a conversion first.
+pub fn is_playable_now(track: &PathBuf) -> bool {
+    match is_rodio_supported(track) {
+        Ok(true) => true,
+        Ok(false) => cache::is_cached(track),
+        Err(_) => false,
+    }
+}
+
+// Processing every track goes through on its way to the sink, shared with the player
+// so changes are heard right away and carry over to the tracks loaded after them.
+#[derive(Clone)]
+pub struct Effects {
+    pub eq: EqGains,
+    pub tempo: SharedTempo,
+}
+
+impl Effects {
+    pub fn apply<S: Source>(&self, source: S) -> TimeStretch<Equalizer<S>> {
+        TimeStretch::new(
+            Equalizer::new(source, Arc::clone(&self.eq)),
+            Arc::clone(&self.tempo),
+        )
     }
 }
 
 pub fn get_sink() -> Result<(OutputStream, Sink)> {
     let stream_handle = rodio::OutputStreamBuilder::open_default_stream()?;
-    let sink = rodio::Sink::connect_new(&stream_handle.mixer());
+    let sink = new_sink(&stream_handle);
 
     Ok((stream_handle, sink))
 }
 
-pub fn get_source(track: PathBuf) -> Result<Decoder<File>> {
-    let file = File::open(track)?;
-    let source = Decoder::new(file)?;
+// Every sink is connected to the stream's mixer, so several of them can play at once.
+pub fn new_sink(stream: &OutputStream) -> Sink {
+    rodio::Sink::connect_new(stream.mixer())
+}
+
+// Built from the file itself rather than a reader so the decoder knows its length
+// and can seek in both directions without decoding from the start.
+pub fn get_source(track: PathBuf) -> Result<Decoder<BufReader<File>>> {
+    let file = File::open(&track)?;
+    let source = Decoder::try_from(file).map_err(|e| {
+        let name = track
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("[Invalid UTF-8 name]");
+        eyre!("Cannot decode {name}: {e}")
+    })?;
 
     Ok(source)
 }
@@ -79,111 +189,299 @@ pub fn choose_multiple_files() -> Option<Vec<PathBuf>> {
     file
 }
 
+pub fn choose_playlist() -> Option<PathBuf> {
+    FileDialog::new()
+        .add_filter("Playlists", &PLAYLIST_FORMATS)
+        .set_directory("~/")
+        .pick_file()
+}
+
+pub fn choose_playlist_destination() -> Option<PathBuf> {
+    let file = FileDialog::new()
+        .add_filter("M3U8 playlist", &["m3u8"])
+        .add_filter("PLS playlist", &["pls"])
+        .add_filter("XSPF playlist", &["xspf"])
+        .set_directory("~/")
+        .set_file_name("queue.m3u8")
+        .save_file();
+
+    // A name typed in without a playlist extension still gets saved as M3U8
+    file.map(|path| match is_playlist(&path) {
+        true => path,
+        false => {
+            let mut name = path.into_os_string();
+            name.push(".m3u8");
+            PathBuf::from(name)
+        }
+    })
+}
+
 pub fn choose_dir() -> Option<PathBuf> {
     let dir = FileDialog::new().pick_folder();
 
     dir
 }
 
-pub fn load_track(sink: &Arc<Mutex<Sink>>, track: &PathBuf) -> Result<()> {
-    let mut track_temp = track.clone();
-    if !is_rodio_supported(&track_temp)? {
-        track_temp = PathBuf::from(CONVERTED_TRACK);
-    }
+// The sink is left paused when `play` is false, so none of the track is heard.
+pub fn load_track(
+    sink: &Arc<Mutex<Sink>>,
+    track: &PathBuf,
+    effects: &Effects,
+    play: bool,
+) -> Result<()> {
+    let track_temp = playable_path(track)?;
 
-    let sink = Arc::clone(sink);
-    thread::spawn(move || {
-        let source = get_source(track_temp).expect("Error obtaining source");
+    // Decode on the caller's thread so the sink contents are known once this returns,
+    // gapless playback relies on counting the sources queued in the sink.
+    let source = effects.apply(get_source(track_temp)?);
 
-        let sink = sink.lock().unwrap();
-        sink.clear();
-        sink.append(source);
+    let sink = sink.lock().unwrap();
+    sink.clear();
+    sink.append(source);
+    if play {
         sink.play();
-    });
+    }
 
     Ok(())
 }
 
-pub fn increase_volume(sink: &Arc<Mutex<Sink>>, amount: f32) {
-    let sink = sink.lock().unwrap();
-    let current_vol = sink.volume().clone();
-    let increased_vol = f32::min(current_vol + amount, 2.0);
-    sink.set_volume(increased_vol);
-}
+// Appends a track behind the one currently playing without clearing the sink,
+// so rodio switches over to it as soon as the current source runs out.
+// Tracks that need converting can only be appended once their conversion is cached.
+pub fn append_track(sink: &Arc<Mutex<Sink>>, track: &PathBuf, effects: &Effects) -> Result<()> {
+    let source = effects.apply(get_source(playable_path(track)?)?);
 
-pub fn decrease_volume(sink: &Arc<Mutex<Sink>>, amount: f32) {
     let sink = sink.lock().unwrap();
-    let current_vol = sink.volume().clone();
-    let decreased_vol = f32::max(current_vol - amount, 0.0);
-    sink.set_volume(decreased_vol);
+    sink.append(source);
+
+    Ok(())
 }
 
-pub fn forward(sink: &Arc<Mutex<Sink>>, track_dur: &Duration, forward_dur: Duration) {
+// Plays a track through an ffmpeg pipe instead of converting it first.
+pub fn load_stream(
+    sink: &Arc<Mutex<Sink>>,
+    track: &Path,
+    effects: &Effects,
+    play: bool,
+) -> Result<()> {
+    let source = effects.apply(StreamSource::new(track.to_path_buf(), Duration::ZERO)?);
+
     let sink = sink.lock().unwrap();
-    let current_pos = sink.get_pos();
-    if current_pos.add(forward_dur) < *track_dur {
-        sink.try_seek(current_pos.add(forward_dur))
-            .expect("Error forwarding");
-    } else if track_dur.sub(current_pos) < forward_dur
-        && track_dur.sub(current_pos) > Duration::from_secs(1)
-    {
-        sink.try_seek(track_dur.sub(Duration::from_secs(1)))
-            .expect("Error forwarding");
+    sink.clear();
+    sink.append(source);
+    if play {
+        sink.play();
     }
+
+    Ok(())
 }
 
-pub fn rewind(sink: &Arc<Mutex<Sink>>, track: &PathBuf, rewind_dur: Duration) -> Result<()> {
-    let mut temp_path = track.clone();
-    if !is_rodio_supported(&temp_path)? {
-        temp_path = PathBuf::from(CONVERTED_TRACK);
+// Only the user's volume is computed here, the sink's volume also carries the
+// track's gain and any crossfade in progress, so the caller applies it.
+pub fn increase_volume(current_vol: f32, amount: f32) -> f32 {
+    f32::min(current_vol + amount, 2.0)
+}
+
+pub fn decrease_volume(current_vol: f32, amount: f32) -> f32 {
+    f32::max(current_vol - amount, 0.0)
+}
+
+// Moves playback of the current track to `target`, given in track time.
+// The sink seeks in place when the source supports it, otherwise the track is reopened.
+// Returns true when it had to be reopened, which drops anything queued behind it in the sink.
+pub fn seek(
+    sink: &Arc<Mutex<Sink>>,
+    track: &PathBuf,
+    target: Duration,
+    effects: &Effects,
+) -> Result<bool> {
+    // The sink counts time played, which the speed scales
+    let sink_target = target.div_f32(effects.tempo.lock().unwrap().speed);
+
+    let in_place = sink.lock().unwrap().try_seek(sink_target);
+    match in_place {
+        Ok(()) => Ok(false),
+        Err(SeekError::NotSupported { .. }) => {
+            reopen_at(sink, track, sink_target, effects)?;
+            Ok(true)
+        }
+        Err(e) => Err(eyre!("Error seeking: {e}")),
     }
+}
 
-    let sink = sink.lock().unwrap();
-    let current_pos = sink.get_pos();
-    let rewinded_pos = current_pos
-        .checked_sub(rewind_dur)
-        .unwrap_or(Duration::new(1, 0));
+fn reopen_at(
+    sink: &Arc<Mutex<Sink>>,
+    track: &PathBuf,
+    sink_target: Duration,
+    effects: &Effects,
+) -> Result<()> {
+    let source = effects.apply(get_source(playable_path(track)?)?);
 
+    let sink = sink.lock().unwrap();
+    let paused = sink.is_paused();
     sink.clear();
-    let source = get_source(temp_path).expect("Error obtaining source");
     sink.append(source);
+    sink.try_seek(sink_target)
+        .map_err(|e| eyre!("Error seeking: {e}"))?;
+    if !paused {
+        sink.play();
+    }
 
-    sink.try_seek(rewinded_pos).expect("Error rewinding");
+    Ok(())
+}
 
-    sink.play();
+// Works out where a "go to" entry points in the track: a timestamp like 1:23:45 or 2:30,
+// a percentage like 50%, or a step from the current position like +90s, -1m30s or +2:00.
+// Targets past the end stop a second short of it, like forwarding does.
+pub fn seek_target(
+    input: &str,
+    current_pos: Duration,
+    track_dur: Option<Duration>,
+) -> Result<Duration> {
+    let input = input.trim();
+    let target = if let Some(percent) = input.strip_suffix('%') {
+        let percent: f32 = percent
+            .trim()
+            .parse()
+            .map_err(|_| eyre!("{input} is not a percentage"))?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(eyre!("percentage must be between 0 and 100"));
+        }
+        let track_dur = track_dur.ok_or_else(|| eyre!("track length is unknown"))?;
+        track_dur.mul_f32(percent / 100.0)
+    } else if let Some(offset) = input.strip_prefix('+') {
+        current_pos + parse_time(offset)?
+    } else if let Some(offset) = input.strip_prefix('-') {
+        current_pos.saturating_sub(parse_time(offset)?)
+    } else {
+        parse_time(input)?
+    };
 
-    Ok(())
+    Ok(match track_dur {
+        Some(track_dur) => target.min(track_dur.saturating_sub(Duration::from_secs(1))),
+        None => target,
+    })
 }
 
-pub fn get_track_duration(track: &PathBuf) -> Result<Duration> {
-    let mut temp_path = track.clone();
-    if !is_rodio_supported(&temp_path)? {
-        temp_path = PathBuf::from(CONVERTED_TRACK)
+// Either h:m:s / m:s, or numbers followed by h, m or s, bare numbers counting as seconds.
+pub fn parse_time(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let invalid = || eyre!("{input} is not a valid time");
+    if input.is_empty() {
+        return Err(eyre!("no time given"));
     }
 
-    let tagged_file = Probe::open(temp_path)
-        .expect("ERROR: Bad path provided!")
-        .read()
-        .expect("ERROR: Failed to read file!");
+    if input.contains(':') {
+        let parts: Vec<&str> = input.split(':').collect();
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        let mut secs = 0.0;
+        for part in parts {
+            let value: f64 = part.trim().parse().map_err(|_| invalid())?;
+            if value.is_sign_negative() {
+                return Err(invalid());
+            }
+            secs = secs * 60.0 + value;
+        }
+        return Duration::try_from_secs_f64(secs).map_err(|_| invalid());
+    }
 
-    Ok(tagged_file.properties().duration())
+    let mut secs = 0.0;
+    let mut number = String::new();
+    for c in input.chars().filter(|c| !c.is_whitespace()) {
+        let unit = match c.to_ascii_lowercase() {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        if value.is_sign_negative() {
+            return Err(invalid());
+        }
+        secs += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        secs += number.parse::<f64>().map_err(|_| invalid())?;
+    }
+
+    Duration::try_from_secs_f64(secs).map_err(|_| invalid())
+}
+
+// A chapter of an audiobook or a long mix, as ffprobe lists it.
+#[derive(Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: Option<String>,
 }
 
-pub fn convert_format(track_path: &PathBuf) {
-    let runtime = Runtime::new().unwrap();
+// Reads the track's chapters with ffprobe, which handles the chapter formats of
+// MP4/M4B, Matroska, Ogg and ID3 alike. Tracks without chapters give an empty list.
+pub fn read_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "chapter=start_time:chapter_tags=title",
+        ])
+        .args(["-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .map_err(|e| eyre!("cannot run ffprobe: {e}"))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(parse_chapters(&String::from_utf8_lossy(&output.stdout)))
+}
 
-    runtime.block_on(async {
-        FFmpegBuilder::convert(track_path.clone(), CONVERTED_TRACK)
-            .audio_filter(AudioFilter::loudnorm())
-            .run()
-            .await
-            .unwrap();
-    });
+// One chapter per line as `start,title`, the title quoted when it holds a comma or a quote.
+fn parse_chapters(output: &str) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = output
+        .lines()
+        .filter_map(|line| {
+            let (start, title) = line.split_once(',').unwrap_or((line, ""));
+            let start = Duration::try_from_secs_f64(start.trim().parse().ok()?).ok()?;
+            let title = match title.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\"\"", "\""),
+                None => title.to_string(),
+            };
+            Some(Chapter {
+                start,
+                title: Some(title).filter(|title| !title.trim().is_empty()),
+            })
+        })
+        .collect();
+    chapters.sort_by_key(|chapter| chapter.start);
+
+    chapters
+}
+
+pub fn get_track_duration(track: &PathBuf) -> Result<Duration> {
+    // Tracks streamed through ffmpeg have no converted copy, read the original instead
+    let temp_path = match playable_path(track) {
+        Ok(path) => path,
+        Err(_) if track.is_file() => track.clone(),
+        Err(e) => return Err(e),
+    };
+
+    let tagged_file = Probe::open(temp_path)?.guess_file_type()?.read()?;
+
+    Ok(tagged_file.properties().duration())
 }
 
 pub fn enqueue_track(path_vec: Vec<PathBuf>, track_queue: &mut VecDeque<PathBuf>) {
     for path in path_vec {
-        if path.is_file() {
+        if is_audio_file(&path) {
             track_queue.push_back(path);
         }
     }
@@ -194,12 +492,8 @@ pub fn enqueue_dir(dir: PathBuf, track_queue: &mut VecDeque<PathBuf>) {
     if let Ok(entries) = fs::read_dir(dir) {
         for entry_result in entries {
             if let Ok(entry) = entry_result {
-                if entry.path().is_file() {
-                    if let Some(extension) = entry.path().extension().and_then(|e| e.to_str()) {
-                        if AUDIO_FORMATS.contains(&extension) {
-                            path_vec.push(entry.path());
-                        }
-                    }
+                if is_audio_file(&entry.path()) {
+                    path_vec.push(entry.path());
                 }
             }
         }
@@ -207,3 +501,134 @@ pub fn enqueue_dir(dir: PathBuf, track_queue: &mut VecDeque<PathBuf>) {
 
     track_queue.extend(path_vec);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_time("1:23:45").unwrap(), secs(5025.0));
+        assert_eq!(parse_time("2:30").unwrap(), secs(150.0));
+        assert_eq!(parse_time(" 0:05 ").unwrap(), secs(5.0));
+        assert_eq!(parse_time("1:30.5").unwrap(), secs(90.5));
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse_time("90s").unwrap(), secs(90.0));
+        assert_eq!(parse_time("1m30s").unwrap(), secs(90.0));
+        assert_eq!(parse_time("1h 2m 3s").unwrap(), secs(3723.0));
+        assert_eq!(parse_time("1.5M").unwrap(), secs(90.0));
+        assert_eq!(parse_time("45").unwrap(), secs(45.0));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for input in [
+            "", "  ", "abc", "1:2:3:4", "1::", "1:x", "m", "1x", "5s3q", "-5", "1:-5", "1m-30s",
+        ] {
+            assert!(parse_time(input).is_err(), "{input:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn seeks_to_percentages() {
+        let dur = Some(secs(200.0));
+        assert_eq!(seek_target("50%", secs(10.0), dur).unwrap(), secs(100.0));
+        assert_eq!(seek_target("0%", secs(10.0), dur).unwrap(), Duration::ZERO);
+        assert!(seek_target("150%", secs(10.0), dur).is_err());
+        assert!(seek_target("-5%", secs(10.0), dur).is_err());
+        assert!(seek_target("half%", secs(10.0), dur).is_err());
+        assert!(seek_target("50%", secs(10.0), None).is_err());
+    }
+
+    #[test]
+    fn seeks_relative_to_the_position() {
+        let dur = Some(secs(600.0));
+        assert_eq!(seek_target("+90s", secs(60.0), dur).unwrap(), secs(150.0));
+        assert_eq!(seek_target("-1m30s", secs(120.0), dur).unwrap(), secs(30.0));
+        assert_eq!(seek_target("+2:00", secs(60.0), dur).unwrap(), secs(180.0));
+        assert!(seek_target("+soon", secs(60.0), dur).is_err());
+        // A sign only goes in front, not on the parts
+        assert!(seek_target("+1m-30s", secs(60.0), dur).is_err());
+        assert!(seek_target("--30s", secs(60.0), dur).is_err());
+    }
+
+    #[test]
+    fn clamps_seeks_to_the_track() {
+        let dur = Some(secs(600.0));
+        // Past the end stops a second short of it
+        assert_eq!(seek_target("20:00", secs(60.0), dur).unwrap(), secs(599.0));
+        assert_eq!(seek_target("+10m", secs(300.0), dur).unwrap(), secs(599.0));
+        assert_eq!(seek_target("100%", secs(0.0), dur).unwrap(), secs(599.0));
+        // Going back past the start stops at the start
+        assert_eq!(
+            seek_target("-10m", secs(30.0), dur).unwrap(),
+            Duration::ZERO
+        );
+        // Without a known length there is nothing to clamp to
+        assert_eq!(
+            seek_target("20:00", secs(60.0), None).unwrap(),
+            secs(1200.0)
+        );
+    }
+
+    #[test]
+    fn skips_known_non_audio_files_without_probing() {
+        let dir = std::env::temp_dir().join(format!("firefly-{}-audio-files", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // A FLAC header, which probing would recognize
+        for name in [
+            "cover.jpg",
+            "rip.LOG",
+            "album.cue",
+            "track",
+            "track.xyz",
+            "song.mp3",
+        ] {
+            fs::write(dir.join(name), b"fLaC\0\0\0\x22").unwrap();
+        }
+
+        assert!(!is_audio_file(&dir.join("cover.jpg")));
+        assert!(!is_audio_file(&dir.join("rip.LOG")));
+        assert!(!is_audio_file(&dir.join("album.cue")));
+        assert!(is_audio_file(&dir.join("track")));
+        assert!(is_audio_file(&dir.join("track.xyz")));
+        assert!(is_audio_file(&dir.join("song.mp3")));
+        assert!(!is_audio_file(&dir.join("missing.mp3")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_chapters() {
+        let chapters = parse_chapters(
+            "0.000000,Opening\n\
+             754.250000,\"Part One, \"\"The Road\"\"\"\n\
+             not a time,Skipped\n\
+             300.000000\n",
+        );
+
+        assert_eq!(
+            chapters,
+            vec![
+                Chapter {
+                    start: Duration::ZERO,
+                    title: Some("Opening".to_string()),
+                },
+                Chapter {
+                    start: secs(300.0),
+                    title: None,
+                },
+                Chapter {
+                    start: secs(754.25),
+                    title: Some("Part One, \"The Road\"".to_string()),
+                },
+            ]
+        );
+    }
+}
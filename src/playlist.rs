@@ -0,0 +1,761 @@
+use color_eyre::eyre::{Result, eyre};
+use quick_xml::{
+    escape::escape,
+    events::{BytesStart, Event},
+    reader::Reader,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{dirs, smart};
+
+pub const PLAYLIST_FORMATS: [&str; 4] = ["m3u", "m3u8", "pls", "xspf"];
+
+// Playlists managed from the player are kept here, named after the playlist:
+// M3U8 files for ones listing tracks, and rule files for smart ones.
+const SAVED_DIR: &str = "playlists";
+const SAVED_EXTENSION: &str = "m3u8";
+const SMART_EXTENSION: &str = "smart";
+
+enum Format {
+    M3u,
+    // Winamp's INI style format, entries numbered from 1.
+    Pls,
+    // XML Shareable Playlist Format, with tracks located by URI.
+    Xspf,
+}
+
+fn format(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "m3u" | "m3u8" => Some(Format::M3u),
+        "pls" => Some(Format::Pls),
+        "xspf" => Some(Format::Xspf),
+        _ => None,
+    }
+}
+
+// A track listed in a playlist, along with the title and duration the playlist gives for it.
+#[derive(Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+// The parts of an entry read so far, an entry without a location is dropped.
+#[derive(Default)]
+struct Fields {
+    location: Option<String>,
+    title: Option<String>,
+    duration: Option<Duration>,
+}
+
+impl Entry {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            title: None,
+            duration: None,
+        }
+    }
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    format(path).is_some()
+}
+
+// Entries come back in playlist order, whether or not their files exist.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let format = format(path).ok_or_else(|| eyre!("not a playlist"))?;
+    let bytes = fs::read(path)?;
+    // Plain .m3u and .pls files are often in a legacy encoding, anything that isn't UTF-8 is replaced
+    let contents = String::from_utf8_lossy(&bytes);
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    match format {
+        Format::M3u => Ok(parse_m3u(&contents, base)),
+        Format::Pls => Ok(parse_pls(&contents, base)),
+        Format::Xspf => parse_xspf(&contents, base),
+    }
+}
+
+// Extended M3U, where an `#EXTINF:seconds,title` line describes the entry following it.
+// Other comment lines are skipped.
+fn parse_m3u(contents: &str, base: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut info: Option<(Option<Duration>, Option<String>)> = None;
+
+    for line in contents.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Some(parse_extinf(extinf));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (duration, title) = info.take().unwrap_or_default();
+        entries.push(Entry {
+            path: resolve(line, base),
+            title,
+            duration,
+        });
+    }
+
+    entries
+}
+
+// `-1` stands for an unknown duration. Attributes like `tvg-id="..."` may sit between
+// the duration and the comma, so the title starts after the first comma outside quotes.
+fn parse_extinf(extinf: &str) -> (Option<Duration>, Option<String>) {
+    let mut in_quotes = false;
+    let comma = extinf.char_indices().find_map(|(idx, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ',' if !in_quotes => Some(idx),
+        _ => None,
+    });
+    let (head, title) = match comma {
+        Some(idx) => (&extinf[..idx], Some(extinf[idx + 1..].trim())),
+        None => (extinf, None),
+    };
+
+    let duration = head
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .filter(|secs| *secs >= 0.0)
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+    let title = title.filter(|title| !title.is_empty()).map(String::from);
+
+    (duration, title)
+}
+
+// `FileN`, `TitleN` and `LengthN` keys describe entry N, with -1 for an unknown length.
+// Entries are listed by number, whatever order the keys appear in.
+fn parse_pls(contents: &str, base: &Path) -> Vec<Entry> {
+    let mut numbered: BTreeMap<u32, Fields> = BTreeMap::new();
+
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim_start_matches('\u{feff}').split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim().to_lowercase(), value.trim());
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let Ok(number) = key[split..].parse() else {
+            continue;
+        };
+
+        let fields = numbered.entry(number).or_default();
+        match &key[..split] {
+            "file" => fields.location = Some(value.to_string()),
+            "title" => fields.title = Some(value.to_string()).filter(|title| !title.is_empty()),
+            "length" => {
+                fields.duration = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| *secs >= 0.0)
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            }
+            _ => {}
+        }
+    }
+
+    numbered
+        .into_values()
+        .filter_map(|fields| {
+            Some(Entry {
+                path: resolve(&fields.location?, base),
+                title: fields.title,
+                duration: fields.duration,
+            })
+        })
+        .collect()
+}
+
+// Reads `location`, `title` and `duration` (in milliseconds) of each `track`, other elements are skipped.
+fn parse_xspf(contents: &str, base: &Path) -> Result<Vec<Entry>> {
+    let mut reader = Reader::from_str(contents);
+    reader.config_mut().trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut track: Option<Fields> = None;
+    // The element whose text is being read, only tracked inside a track
+    let mut element: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                let name = local_name(&start);
+                match name.as_str() {
+                    "track" => track = Some(Fields::default()),
+                    _ if track.is_some() => element = Some(name),
+                    _ => {}
+                }
+            }
+            Event::End(end) => {
+                let name = String::from_utf8_lossy(end.local_name().as_ref()).into_owned();
+                if name == "track"
+                    && let Some(fields) = track.take()
+                    && let Some(location) = fields.location
+                {
+                    entries.push(Entry {
+                        path: resolve_uri(&location, base),
+                        title: fields.title,
+                        duration: fields.duration,
+                    });
+                }
+                element = None;
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+                read_track_field(&mut track, element.as_deref(), text);
+            }
+            Event::CData(text) => {
+                let text = text.decode()?.into_owned();
+                read_track_field(&mut track, element.as_deref(), text);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+fn local_name(start: &BytesStart) -> String {
+    String::from_utf8_lossy(start.local_name().as_ref()).into_owned()
+}
+
+fn read_track_field(track: &mut Option<Fields>, element: Option<&str>, text: String) {
+    let Some(fields) = track else {
+        return;
+    };
+    match element {
+        Some("location") => fields.location = Some(text),
+        Some("title") => fields.title = Some(text).filter(|title| !title.is_empty()),
+        Some("duration") => fields.duration = text.parse().ok().map(Duration::from_millis),
+        _ => {}
+    }
+}
+
+// Playlist locations are paths relative to the playlist, absolute paths, or file:// URLs.
+fn resolve(location: &str, base: &Path) -> PathBuf {
+    if let Some(path) = location.strip_prefix("file://") {
+        // Drops the host part of file://host/path, which is empty for local files
+        let path = &path[path.find('/').unwrap_or(0)..];
+        return PathBuf::from(percent_decode(path));
+    }
+
+    let path = PathBuf::from(location);
+    match path.is_absolute() || location.contains("://") {
+        true => path,
+        false => base.join(path),
+    }
+}
+
+// Relative URIs in XSPF are percent encoded like absolute ones.
+fn resolve_uri(location: &str, base: &Path) -> PathBuf {
+    match location.contains("://") {
+        true => resolve(location, base),
+        false => resolve(&percent_decode(location), base),
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match (byte, tail) {
+            (b'%', [high, low, ..]) => std::str::from_utf8(&[*high, *low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// The format follows the file's extension, M3U8 when it has none of the known ones.
+// Tracks inside the playlist's directory are stored relative to it,
+// so the folder can be moved along with the playlist.
+pub fn save(path: &Path, entries: &[Entry]) -> Result<()> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let contents = match format(path) {
+        Some(Format::Pls) => write_pls(entries, base)?,
+        Some(Format::Xspf) => write_xspf(entries, base)?,
+        Some(Format::M3u) | None => write_m3u(entries, base)?,
+    };
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+fn write_m3u(entries: &[Entry], base: &Path) -> Result<String> {
+    let mut contents = String::from("#EXTM3U\n");
+    for entry in entries {
+        let secs = entry
+            .duration
+            .map_or(-1, |duration| duration.as_secs() as i64);
+        contents.push_str(&format!(
+            "#EXTINF:{secs},{}\n{}\n",
+            entry_title(entry),
+            location(entry, base)?
+        ));
+    }
+
+    Ok(contents)
+}
+
+fn write_pls(entries: &[Entry], base: &Path) -> Result<String> {
+    let mut contents = String::from("[playlist]\n");
+    for (idx, entry) in entries.iter().enumerate() {
+        let number = idx + 1;
+        let secs = entry
+            .duration
+            .map_or(-1, |duration| duration.as_secs() as i64);
+        contents.push_str(&format!(
+            "File{number}={}\nTitle{number}={}\nLength{number}={secs}\n",
+            location(entry, base)?,
+            entry_title(entry)
+        ));
+    }
+    contents.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+
+    Ok(contents)
+}
+
+fn write_xspf(entries: &[Entry], base: &Path) -> Result<String> {
+    let mut contents = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for entry in entries {
+        let location = location(entry, base)?;
+        // URLs are written as they are, only local paths are turned into URIs
+        let uri = if location.contains("://") {
+            location.to_string()
+        } else if Path::new(location).is_absolute() {
+            format!("file://{}", percent_encode(location))
+        } else {
+            percent_encode(location)
+        };
+
+        contents.push_str("    <track>\n");
+        contents.push_str(&format!("      <location>{}</location>\n", escape(uri)));
+        contents.push_str(&format!(
+            "      <title>{}</title>\n",
+            escape(entry_title(entry))
+        ));
+        if let Some(duration) = entry.duration {
+            contents.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                duration.as_millis()
+            ));
+        }
+        contents.push_str("    </track>\n");
+    }
+    contents.push_str("  </trackList>\n</playlist>\n");
+
+    Ok(contents)
+}
+
+fn location<'a>(entry: &'a Entry, base: &Path) -> Result<&'a str> {
+    let location = entry.path.strip_prefix(base).unwrap_or(&entry.path);
+    location
+        .to_str()
+        .ok_or_else(|| eyre!("{} is not a valid UTF-8 path", entry.path.display()))
+}
+
+fn entry_title(entry: &Entry) -> String {
+    entry
+        .title
+        .clone()
+        .unwrap_or_else(|| default_title(&entry.path))
+}
+
+fn percent_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn default_title(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// A playlist kept in the data directory.
+#[derive(Clone, PartialEq)]
+pub struct Saved {
+    pub name: String,
+    // Filled from the library by its rules each time it is opened.
+    pub smart: bool,
+}
+
+impl Saved {
+    pub fn new(name: &str, smart: bool) -> Self {
+        Self {
+            name: name.trim().to_string(),
+            smart,
+        }
+    }
+
+    // The name becomes the file name, so it can't contain path separators.
+    pub fn path(&self) -> Result<PathBuf> {
+        let name = &self.name;
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(eyre!("\"{name}\" is not a valid playlist name"));
+        }
+        let extension = match self.smart {
+            true => SMART_EXTENSION,
+            false => SAVED_EXTENSION,
+        };
+
+        Ok(saved_dir()?.join(format!("{name}.{extension}")))
+    }
+}
+
+fn saved_dir() -> Result<PathBuf> {
+    Ok(dirs::data_dir()?.join(SAVED_DIR))
+}
+
+// The saved playlists, sorted by name.
+pub fn list_saved() -> Result<Vec<Saved>> {
+    let dir = saved_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut playlists: Vec<Saved> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let smart = match path.extension()?.to_str()? {
+                SAVED_EXTENSION => false,
+                SMART_EXTENSION => true,
+                _ => return None,
+            };
+            Some(Saved::new(path.file_stem()?.to_str()?, smart))
+        })
+        .collect();
+    playlists.sort_by_key(|saved| saved.name.to_lowercase());
+
+    Ok(playlists)
+}
+
+// New smart playlists start without rules, matching the whole library.
+pub fn create_saved(saved: &Saved) -> Result<()> {
+    let path = saved.path()?;
+    if path.exists() {
+        return Err(eyre!("a playlist named \"{}\" already exists", saved.name));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    match saved.smart {
+        true => smart::save(&path, ""),
+        false => save(&path, &[]),
+    }
+}
+
+pub fn rename_saved(saved: &Saved, new_name: &str) -> Result<()> {
+    let new_path = Saved::new(new_name, saved.smart).path()?;
+    if new_path.exists() {
+        return Err(eyre!(
+            "a playlist named \"{}\" already exists",
+            new_name.trim()
+        ));
+    }
+    fs::rename(saved.path()?, new_path)?;
+
+    Ok(())
+}
+
+pub fn delete_saved(saved: &Saved) -> Result<()> {
+    fs::remove_file(saved.path()?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type EntryFields = (PathBuf, Option<String>, Option<Duration>);
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    // A fresh directory per test, so tests running in parallel don't share files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("firefly-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fields(entries: &[Entry]) -> Vec<EntryFields> {
+        entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.title.clone(), entry.duration))
+            .collect()
+    }
+
+    fn expected(path: PathBuf, title: &str, secs: Option<f64>) -> EntryFields {
+        (
+            path,
+            Some(title.to_string()),
+            secs.map(Duration::from_secs_f64),
+        )
+    }
+
+    // Copies the fixture into a directory of its own, loads it, saves it under `saved_name`
+    // next to it and loads that, returning the entries read both times.
+    fn round_trip(
+        fixture_name: &str,
+        saved_name: &str,
+    ) -> (PathBuf, Vec<EntryFields>, Vec<EntryFields>) {
+        let dir = temp_dir(saved_name);
+        let original = dir.join(fixture_name);
+        fs::copy(fixture(fixture_name), &original).unwrap();
+
+        let loaded = load(&original).unwrap();
+        let saved = dir.join(saved_name);
+        save(&saved, &loaded).unwrap();
+        let reloaded = load(&saved).unwrap();
+
+        (dir, fields(&loaded), fields(&reloaded))
+    }
+
+    #[test]
+    fn reads_extinf_lines() {
+        let entries = parse_m3u(
+            "\u{feff}#EXTM3U\n\
+             #EXTINF:-1 tvg-name=\"News, Weather\" group-title=\"A,B\",Morning, Live\n\
+             http://example.com/live\n\
+             #EXTINF:12.5,\n\
+             clip.ogg\n\
+             #EXTINF:garbage\n\
+             no-title.mp3\n\
+             untitled.mp3\n",
+            Path::new("/base"),
+        );
+
+        assert_eq!(
+            fields(&entries),
+            vec![
+                (
+                    PathBuf::from("http://example.com/live"),
+                    Some("Morning, Live".to_string()),
+                    None
+                ),
+                (
+                    PathBuf::from("/base/clip.ogg"),
+                    None,
+                    Some(Duration::from_secs_f64(12.5))
+                ),
+                (PathBuf::from("/base/no-title.mp3"), None, None),
+                (PathBuf::from("/base/untitled.mp3"), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let base = Path::new("/lists");
+        assert_eq!(resolve("a/b.mp3", base), PathBuf::from("/lists/a/b.mp3"));
+        assert_eq!(resolve("/abs/b.mp3", base), PathBuf::from("/abs/b.mp3"));
+        assert_eq!(
+            resolve("file:///srv/My%20Music/%E2%99%AB.flac", base),
+            PathBuf::from("/srv/My Music/♫.flac")
+        );
+        assert_eq!(
+            resolve("file://localhost/srv/a.mp3", base),
+            PathBuf::from("/srv/a.mp3")
+        );
+        // A stray percent sign is kept as it is
+        assert_eq!(percent_decode("100%_%zz"), "100%_%zz");
+    }
+
+    #[test]
+    fn loads_m3u8_fixture() {
+        let path = fixture("sample.m3u8");
+        let base = path.parent().unwrap();
+
+        assert_eq!(
+            fields(&load(&path).unwrap()),
+            vec![
+                expected(base.join("music/01 - Opening.flac"), "Opening", Some(182.0)),
+                expected("/srv/radio/stream.ogg".into(), "Live Stream", None),
+                expected(
+                    "/srv/music/Café Tacuba/01 Ojalá.mp3".into(),
+                    "Ojalá que llueva café",
+                    Some(263.0)
+                ),
+                expected(
+                    base.join("music/sub dir/04 Closing.flac"),
+                    "Closing",
+                    Some(301.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_m3u8() {
+        let (dir, loaded, reloaded) = round_trip("sample.m3u8", "saved.m3u8");
+        assert_eq!(loaded, reloaded);
+
+        // Tracks beside the playlist stay relative, others are written as absolute paths
+        let saved = fs::read_to_string(dir.join("saved.m3u8")).unwrap();
+        assert!(saved.contains("\nmusic/01 - Opening.flac\n"));
+        assert!(saved.contains("\n/srv/music/Café Tacuba/01 Ojalá.mp3\n"));
+        assert!(saved.contains("#EXTINF:-1,Live Stream\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_pls_fixture() {
+        let path = fixture("sample.pls");
+        let base = path.parent().unwrap();
+
+        // Numbered keys out of order still give entries in number order
+        assert_eq!(
+            fields(&load(&path).unwrap()),
+            vec![
+                expected(base.join("music/01 - Opening.flac"), "Opening", Some(182.0)),
+                expected("/srv/radio/stream.ogg".into(), "Live Stream", None),
+                expected(
+                    "/srv/music/Café Tacuba/01 Ojalá.mp3".into(),
+                    "Ojalá que llueva café",
+                    Some(263.0)
+                ),
+                expected(
+                    base.join("music/sub dir/04 Closing.flac"),
+                    "Closing",
+                    Some(301.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_pls() {
+        let (dir, loaded, reloaded) = round_trip("sample.pls", "saved.pls");
+        assert_eq!(loaded, reloaded);
+
+        let saved = fs::read_to_string(dir.join("saved.pls")).unwrap();
+        assert!(saved.contains("File1=music/01 - Opening.flac\n"));
+        assert!(saved.contains("Length2=-1\n"));
+        assert!(saved.contains("NumberOfEntries=4\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_xspf_fixture() {
+        let path = fixture("sample.xspf");
+        let base = path.parent().unwrap();
+
+        assert_eq!(
+            fields(&load(&path).unwrap()),
+            vec![
+                expected(
+                    base.join("music/01 - Opening.flac"),
+                    "Opening & <Intro>",
+                    Some(182.5)
+                ),
+                expected(
+                    "/srv/music/Café Tacuba/01 Ojalá.mp3".into(),
+                    "Ojalá que llueva café",
+                    Some(263.0)
+                ),
+                expected(
+                    "/srv/radio/stream one.ogg".into(),
+                    "Rock & Roll Radio",
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_xspf() {
+        let (dir, loaded, reloaded) = round_trip("sample.xspf", "saved.xspf");
+        assert_eq!(loaded, reloaded);
+
+        let saved = fs::read_to_string(dir.join("saved.xspf")).unwrap();
+        assert!(saved.contains("<location>music/01%20-%20Opening.flac</location>"));
+        assert!(saved.contains(
+            "<location>file:///srv/music/Caf%C3%A9%20Tacuba/01%20Ojal%C3%A1.mp3</location>"
+        ));
+        assert!(saved.contains("<title>Opening &amp; &lt;Intro&gt;</title>"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn round_trips_xspf_urls() {
+        let dir = temp_dir("xspf-urls");
+        let url = "http://radio.example.com:8000/live?format=ogg&q=a%20b";
+        let entries = vec![Entry {
+            path: PathBuf::from(url),
+            title: Some("Live".to_string()),
+            duration: None,
+        }];
+
+        let saved = dir.join("urls.xspf");
+        save(&saved, &entries).unwrap();
+        let contents = fs::read_to_string(&saved).unwrap();
+        assert!(contents.contains(
+            "<location>http://radio.example.com:8000/live?format=ogg&amp;q=a%20b</location>"
+        ));
+        assert_eq!(fields(&load(&saved).unwrap()), fields(&entries));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn converts_between_formats() {
+        // Whole seconds survive every format, so a PLS saved as XSPF and back is unchanged
+        let (dir, loaded, as_xspf) = round_trip("sample.pls", "converted.xspf");
+        assert_eq!(loaded, as_xspf);
+
+        let back = dir.join("back.pls");
+        save(&back, &load(&dir.join("converted.xspf")).unwrap()).unwrap();
+        assert_eq!(loaded, fields(&load(&back).unwrap()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
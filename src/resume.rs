@@ -0,0 +1,100 @@
+use color_eyre::eyre::Result;
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use crate::{config, dirs};
+
+// Positions are remembered for tracks at least this long, unless the config file says otherwise.
+const DEFAULT_THRESHOLD: Duration = Duration::from_secs(20 * 60);
+// A track counts as finished once played to within this much of its end.
+pub const FINISHED_MARGIN: Duration = Duration::from_secs(60);
+
+const RESUME_FILE: &str = "resume_positions";
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ResumePoint {
+    pub position: Duration,
+    pub duration: Duration,
+    pub finished: bool,
+}
+
+impl ResumePoint {
+    pub fn new(position: Duration, duration: Duration) -> Self {
+        Self {
+            position,
+            duration,
+            finished: duration.saturating_sub(position) <= FINISHED_MARGIN,
+        }
+    }
+
+    pub fn progress(&self) -> f64 {
+        match self.duration.is_zero() {
+            true => 0.0,
+            false => self.position.as_secs_f64() / self.duration.as_secs_f64(),
+        }
+    }
+}
+
+// The shortest track whose position is remembered, read from `resume_threshold_minutes = N`
+// in the config file.
+pub fn load_threshold() -> Duration {
+    config::value("resume_threshold_minutes")
+        .and_then(|minutes| minutes.parse::<f64>().ok())
+        .and_then(|minutes| Duration::try_from_secs_f64(minutes * 60.0).ok())
+        .unwrap_or(DEFAULT_THRESHOLD)
+}
+
+fn resume_path() -> Result<PathBuf> {
+    Ok(dirs::state_dir()?.join(RESUME_FILE))
+}
+
+// Stored one track per line as `position duration finished path`, lines that don't parse are skipped.
+pub fn load() -> HashMap<PathBuf, ResumePoint> {
+    let Ok(contents) = resume_path().and_then(|path| Ok(fs::read_to_string(path)?)) else {
+        return HashMap::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let position = fields.next()?.parse().ok()?;
+            let duration = fields.next()?.parse().ok()?;
+            let finished = fields.next()? == "1";
+            let path = PathBuf::from(fields.next()?);
+            Some((
+                path,
+                ResumePoint {
+                    position: Duration::try_from_secs_f64(position).ok()?,
+                    duration: Duration::try_from_secs_f64(duration).ok()?,
+                    finished,
+                },
+            ))
+        })
+        .collect()
+}
+
+pub fn save(points: &HashMap<PathBuf, ResumePoint>) -> Result<()> {
+    let path = resume_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let contents: String = points
+        .iter()
+        .filter_map(|(track, point)| {
+            Some(format!(
+                "{} {} {} {}\n",
+                point.position.as_secs_f64(),
+                point.duration.as_secs_f64(),
+                point.finished as u8,
+                track.to_str()?
+            ))
+        })
+        .collect();
+
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(temp, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,416 @@
+use color_eyre::eyre::{Result, eyre};
+use lofty::{
+    config::WriteOptions,
+    file::{AudioFile, TaggedFileExt},
+    probe::Probe,
+    tag::{ItemKey, Tag},
+};
+use rodio::Source;
+use std::{
+    f64::consts::PI,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{eq::Biquad, gain::GainTags, player};
+
+// ReplayGain 2.0 reference loudness.
+const REFERENCE_LUFS: f64 = -18.0;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+// Loudness is measured over 400ms blocks overlapping by 75%,
+// built from 100ms sub-blocks.
+const SUB_BLOCKS_PER_SECOND: u32 = 10;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+// True peak is estimated by 4x oversampling with a windowed sinc interpolator.
+const OVERSAMPLING: usize = 4;
+const INTERPOLATION_TAPS: usize = 12;
+
+// Loudness measured from a single track.
+pub struct Measurement {
+    // Mean square of each gating block, K-weighted and summed over channels.
+    blocks: Vec<f64>,
+    pub true_peak: f32,
+}
+
+impl Measurement {
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        integrated_lufs(&self.blocks)
+    }
+}
+
+// A scan running on its own thread, progress messages come back over `messages`.
+pub struct Scan {
+    pub messages: Receiver<String>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Scan {
+    pub fn start(paths: Vec<PathBuf>) -> Self {
+        let (sender, messages) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let cancel = Arc::clone(&cancel);
+            thread::spawn(move || {
+                scan_paths(paths, &cancel, &mut |message| {
+                    let _ = sender.send(message);
+                });
+            })
+        };
+
+        Self {
+            messages,
+            cancel,
+            handle: Some(handle),
+        }
+    }
+
+    // Tags are written in place, so the file being written is finished before this returns
+    // and no file is left half-written.
+    pub fn cancel(mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Scans files and directories, writing ReplayGain tags to every track that has none.
+// Each directory is treated as an album, like when it gets queued.
+// Stops partway through the track being measured once `cancel` is set.
+pub fn scan_paths(paths: Vec<PathBuf>, cancel: &AtomicBool, report: &mut dyn FnMut(String)) {
+    for path in paths {
+        if cancel.load(Ordering::SeqCst) {
+            return;
+        }
+        if path.is_dir() {
+            let mut files: Vec<PathBuf> = match fs::read_dir(&path) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| player::is_audio_file(path))
+                    .collect(),
+                Err(e) => {
+                    report(format!("Cannot read {}: {e}", path.display()));
+                    continue;
+                }
+            };
+            files.sort();
+            scan_album(&files, true, cancel, report);
+        } else {
+            scan_album(&[path], false, cancel, report);
+        }
+    }
+}
+
+fn scan_album(files: &[PathBuf], album: bool, cancel: &AtomicBool, report: &mut dyn FnMut(String)) {
+    let tagged: Vec<bool> = files
+        .iter()
+        .map(|file| GainTags::read(file).track_gain.is_some())
+        .collect();
+    if tagged.iter().all(|tagged| *tagged) {
+        for file in files {
+            report(format!("{}: already tagged, skipped", file_name(file)));
+        }
+        return;
+    }
+
+    // Tracks that already have tags are still measured, the album gain has to cover every track
+    let mut measured: Vec<(&PathBuf, Measurement, bool)> = Vec::new();
+    for (idx, (file, tagged)) in files.iter().zip(tagged).enumerate() {
+        if cancel.load(Ordering::SeqCst) {
+            return;
+        }
+        let name = file_name(file);
+        let count = format!("[{}/{}]", idx + 1, files.len());
+
+        match player::is_rodio_supported(file) {
+            Ok(true) => {}
+            Ok(false) => {
+                report(format!("{count} {name}: format not decodable, skipped"));
+                continue;
+            }
+            Err(e) => {
+                report(format!("{count} {name}: {e}"));
+                continue;
+            }
+        }
+
+        match tagged {
+            true => report(format!("{count} Measuring {name} for the album gain...")),
+            false => report(format!("{count} Scanning {name}...")),
+        }
+        match measure(file, cancel) {
+            Ok(measurement) => measured.push((file, measurement, tagged)),
+            Err(_) if cancel.load(Ordering::SeqCst) => return,
+            Err(e) => report(format!("{count} {name}: {e}")),
+        }
+    }
+
+    let album_gain = if album {
+        let blocks: Vec<f64> = measured
+            .iter()
+            .flat_map(|(_, measurement, _)| measurement.blocks.iter().copied())
+            .collect();
+        let peak = measured
+            .iter()
+            .map(|(_, measurement, _)| measurement.true_peak)
+            .fold(0.0, f32::max);
+        integrated_lufs(&blocks).map(|lufs| (REFERENCE_LUFS - lufs, peak))
+    } else {
+        None
+    };
+
+    // Tagged tracks keep their track gain but get the new album gain, so the whole album
+    // shares one
+    for (file, measurement, tagged) in measured {
+        if cancel.load(Ordering::SeqCst) {
+            return;
+        }
+        let name = file_name(file);
+        if tagged {
+            let Some(album_gain) = album_gain else {
+                continue;
+            };
+            match write_tags(file, None, Some(album_gain)) {
+                Ok(()) => report(format!(
+                    "{name}: already tagged, album gain {:+.2} dB",
+                    album_gain.0
+                )),
+                Err(e) => report(format!("{name}: cannot write tags: {e}")),
+            }
+            continue;
+        }
+
+        let Some(lufs) = measurement.integrated_lufs() else {
+            report(format!("{name}: too quiet to measure, skipped"));
+            continue;
+        };
+
+        let track_gain = (REFERENCE_LUFS - lufs, measurement.true_peak);
+        match write_tags(file, Some(track_gain), album_gain) {
+            Ok(()) => report(format!(
+                "{name}: {lufs:.2} LUFS, gain {:+.2} dB, peak {:.6}",
+                track_gain.0, track_gain.1
+            )),
+            Err(e) => report(format!("{name}: cannot write tags: {e}")),
+        }
+    }
+}
+
+// Decodes a track with rodio and measures its EBU R128 integrated loudness and true peak.
+// Gives up with an error once `cancel` is set, checked after every sub-block.
+pub fn measure(path: &Path, cancel: &AtomicBool) -> Result<Measurement> {
+    let source = player::get_source(path.to_path_buf())?;
+    let channels = source.channels() as usize;
+    let sample_rate = source.sample_rate();
+    if channels == 0 || sample_rate == 0 {
+        return Err(eyre!("track has no audio"));
+    }
+
+    let weights: Vec<f64> = (0..channels)
+        .map(|ch| channel_weight(ch, channels))
+        .collect();
+    let mut filters: Vec<KWeighting> = (0..channels)
+        .map(|_| KWeighting::new(sample_rate as f64))
+        .collect();
+    let mut peaks: Vec<TruePeak> = (0..channels).map(|_| TruePeak::new()).collect();
+
+    let frames_per_sub_block = (sample_rate / SUB_BLOCKS_PER_SECOND) as usize;
+    let mut sub_blocks: Vec<f64> = Vec::new();
+    let mut sub_block_energy = 0.0;
+    let mut sub_block_frames = 0;
+
+    let mut frame: Vec<f32> = Vec::with_capacity(channels);
+    for sample in source {
+        frame.push(sample);
+        if frame.len() < channels {
+            continue;
+        }
+
+        for (ch, sample) in frame.drain(..).enumerate() {
+            peaks[ch].push(sample);
+            let weighted = filters[ch].process(sample as f64);
+            sub_block_energy += weights[ch] * weighted * weighted;
+        }
+
+        sub_block_frames += 1;
+        if sub_block_frames == frames_per_sub_block {
+            sub_blocks.push(sub_block_energy / frames_per_sub_block as f64);
+            sub_block_energy = 0.0;
+            sub_block_frames = 0;
+            if cancel.load(Ordering::SeqCst) {
+                return Err(eyre!("cancelled"));
+            }
+        }
+    }
+
+    let blocks = sub_blocks
+        .windows(SUB_BLOCKS_PER_BLOCK)
+        .map(|window| window.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64)
+        .collect();
+    let true_peak = peaks.iter().map(|peak| peak.max).fold(0.0, f32::max);
+
+    Ok(Measurement { blocks, true_peak })
+}
+
+// Gated integrated loudness over a set of blocks, as in ITU-R BS.1770.
+fn integrated_lufs(blocks: &[f64]) -> Option<f64> {
+    let mean_above = |gate: f64| -> Option<f64> {
+        let gated: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|energy| block_lufs(*energy) > gate)
+            .collect();
+        match gated.is_empty() {
+            true => None,
+            false => Some(gated.iter().sum::<f64>() / gated.len() as f64),
+        }
+    };
+
+    let relative_gate = block_lufs(mean_above(ABSOLUTE_GATE_LUFS)?) + RELATIVE_GATE_LU;
+    let gate = relative_gate.max(ABSOLUTE_GATE_LUFS);
+
+    Some(block_lufs(mean_above(gate)?))
+}
+
+fn block_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.max(f64::MIN_POSITIVE).log10()
+}
+
+// Surround channels of 5.1 count a bit more and the LFE channel not at all.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn write_tags(path: &Path, track: Option<(f64, f32)>, album: Option<(f64, f32)>) -> Result<()> {
+    let mut tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or_else(|| eyre!("file does not support tags"))?;
+
+    if let Some((gain, peak)) = track {
+        tag.insert_text(ItemKey::ReplayGainTrackGain, format!("{:.2} dB", gain));
+        tag.insert_text(ItemKey::ReplayGainTrackPeak, format!("{:.6}", peak));
+    }
+    if let Some((gain, peak)) = album {
+        tag.insert_text(ItemKey::ReplayGainAlbumGain, format!("{:.2} dB", gain));
+        tag.insert_text(ItemKey::ReplayGainAlbumPeak, format!("{:.6}", peak));
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+
+    Ok(())
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("[Invalid UTF-8 name]")
+}
+
+// The BS.1770 K-weighting curve, a high shelf followed by a high pass,
+// with coefficients derived for the track's sample rate.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(g / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+struct TruePeak {
+    history: [f32; INTERPOLATION_TAPS],
+    taps: [[f32; INTERPOLATION_TAPS]; OVERSAMPLING - 1],
+    max: f32,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        let half = (INTERPOLATION_TAPS / 2) as f64;
+        let mut taps = [[0.0; INTERPOLATION_TAPS]; OVERSAMPLING - 1];
+        for (phase, phase_taps) in taps.iter_mut().enumerate() {
+            let fraction = (phase + 1) as f64 / OVERSAMPLING as f64;
+            for (k, tap) in phase_taps.iter_mut().enumerate() {
+                // Distance from the interpolated point to the sample held at history[k]
+                let t = (INTERPOLATION_TAPS - 1 - k) as f64 - half + fraction;
+                let sinc = match t == 0.0 {
+                    true => 1.0,
+                    false => (PI * t).sin() / (PI * t),
+                };
+                let window = 0.5 + 0.5 * (PI * t / half).cos();
+                *tap = (sinc * window) as f32;
+            }
+        }
+
+        Self {
+            history: [0.0; INTERPOLATION_TAPS],
+            taps,
+            max: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.history.rotate_left(1);
+        self.history[INTERPOLATION_TAPS - 1] = sample;
+        self.max = self.max.max(sample.abs());
+
+        for phase_taps in &self.taps {
+            let interpolated: f32 = self
+                .history
+                .iter()
+                .zip(phase_taps)
+                .map(|(sample, tap)| sample * tap)
+                .sum();
+            self.max = self.max.max(interpolated.abs());
+        }
+    }
+}
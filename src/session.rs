@@ -0,0 +1,93 @@
+use color_eyre::eyre::Result;
+use std::{fs, path::PathBuf, time::Duration};
+
+use crate::{app::RepeatMode, dirs};
+
+const SESSION_FILE: &str = "session";
+
+// What the player was doing when it was last saved, restored on the next start.
+pub struct Session {
+    pub track: Option<PathBuf>,
+    pub position: Duration,
+    pub queue: Vec<PathBuf>,
+    pub volume: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+fn session_path() -> Result<PathBuf> {
+    Ok(dirs::state_dir()?.join(SESSION_FILE))
+}
+
+// The session is stored one `key = value` per line, with a `queue` line for each queued track.
+// Unknown keys and values that don't parse are skipped.
+pub fn load() -> Option<Session> {
+    let contents = fs::read_to_string(session_path().ok()?).ok()?;
+
+    let mut session = Session {
+        track: None,
+        position: Duration::ZERO,
+        queue: Vec::new(),
+        volume: 1.0,
+        repeat: RepeatMode::Off,
+        shuffle: false,
+    };
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        match key {
+            "track" => session.track = Some(PathBuf::from(value)),
+            "position" => {
+                if let Ok(secs) = value.parse() {
+                    session.position = Duration::try_from_secs_f64(secs).unwrap_or_default();
+                }
+            }
+            "queue" => session.queue.push(PathBuf::from(value)),
+            "volume" => session.volume = value.parse().unwrap_or(session.volume),
+            "repeat" => {
+                session.repeat = match value {
+                    "one" => RepeatMode::One,
+                    "queue" => RepeatMode::Queue,
+                    _ => RepeatMode::Off,
+                }
+            }
+            "shuffle" => session.shuffle = value == "true",
+            _ => {}
+        }
+    }
+
+    Some(session)
+}
+
+// Written to a temporary file first, so quitting halfway through a save keeps the previous session.
+pub fn save(session: &Session) -> Result<()> {
+    let path = session_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let repeat = match session.repeat {
+        RepeatMode::Off => "off",
+        RepeatMode::One => "one",
+        RepeatMode::Queue => "queue",
+    };
+    let mut contents = format!(
+        "volume = {}\nrepeat = {repeat}\nshuffle = {}\n",
+        session.volume, session.shuffle
+    );
+    // Paths that aren't valid UTF-8 can't be written back as they are, so they are left out
+    if let Some(track) = session.track.as_ref().and_then(|track| track.to_str()) {
+        contents.push_str(&format!("track = {track}\n"));
+        contents.push_str(&format!("position = {}\n", session.position.as_secs_f64()));
+    }
+    for track in session.queue.iter().filter_map(|track| track.to_str()) {
+        contents.push_str(&format!("queue = {track}\n"));
+    }
+
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(temp, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,703 @@
+use color_eyre::eyre::{Result, eyre};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    library::{PlayStats, TrackInfo},
+    player,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// A smart playlist's rules: conditions joined by AND and OR, AND binding tighter, e.g.
+//   genre = "Jazz" AND year < 1970 OR rating >= 4 AND NOT played in 30 days
+// No rules at all matches every track.
+pub struct Rules {
+    // Any group matching is enough, a group matches when all of its conditions do.
+    groups: Vec<Vec<Condition>>,
+}
+
+struct Condition {
+    negated: bool,
+    test: Test,
+}
+
+enum Test {
+    Text(TextField, Op, String),
+    Number(NumberField, Op, f64),
+    Played(Period),
+    Added(Period),
+}
+
+// How far back `played` and `added` look.
+#[derive(Clone, Copy)]
+enum Period {
+    // This long before now.
+    Last(Duration),
+    // Since the current day, week, month or year began, in local time.
+    Current(CalendarUnit),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CalendarUnit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(Clone, Copy)]
+enum TextField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Path,
+}
+
+#[derive(Clone, Copy)]
+enum NumberField {
+    Year,
+    TrackNumber,
+    Rating,
+    Plays,
+    // In seconds.
+    Duration,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // Text containing the value, ignoring case.
+    Contains,
+}
+
+impl Rules {
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        let mut groups = vec![Vec::new()];
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+
+        while tokens.peek().is_some() {
+            let condition = parse_condition(&mut tokens)?;
+            groups.last_mut().unwrap().push(condition);
+
+            match tokens.next().map(str::to_lowercase).as_deref() {
+                None => break,
+                Some("and") => {}
+                Some("or") => groups.push(Vec::new()),
+                Some(other) => return Err(eyre!("expected AND or OR, found \"{other}\"")),
+            }
+            if tokens.peek().is_none() {
+                return Err(eyre!("rules end in AND or OR"));
+            }
+        }
+
+        Ok(Self { groups })
+    }
+
+    pub fn matches(&self, track: &TrackInfo, stats: Option<&PlayStats>, now: SystemTime) -> bool {
+        self.groups.iter().any(|group| {
+            group
+                .iter()
+                .all(|condition| condition.matches(track, stats, now))
+        })
+    }
+}
+
+impl Condition {
+    fn matches(&self, track: &TrackInfo, stats: Option<&PlayStats>, now: SystemTime) -> bool {
+        let within = |time: Option<SystemTime>, period: Period| {
+            let start = match period {
+                Period::Last(length) => now.checked_sub(length),
+                Period::Current(unit) => calendar_start(now, unit),
+            };
+            match (time, start) {
+                (Some(time), Some(start)) => start <= time && time <= now,
+                _ => false,
+            }
+        };
+
+        let result = match &self.test {
+            Test::Text(field, op, value) => {
+                let text = match field {
+                    TextField::Title => track.title.clone(),
+                    TextField::Artist => track.artist.clone(),
+                    TextField::Album => track.album.clone(),
+                    TextField::Genre => track.genre.clone(),
+                    TextField::Path => Some(track.path.to_string_lossy().into_owned()),
+                };
+                // Tracks missing the field only match "is not"
+                match text {
+                    Some(text) => compare_text(&text, *op, value),
+                    None => *op == Op::Ne,
+                }
+            }
+            Test::Number(field, op, value) => {
+                let number = match field {
+                    NumberField::Year => track.year.map(|year| year as f64),
+                    NumberField::TrackNumber => track.track_number.map(|number| number as f64),
+                    NumberField::Rating => track.rating.map(|rating| rating as f64),
+                    NumberField::Plays => Some(stats.map_or(0, |stats| stats.plays) as f64),
+                    NumberField::Duration => track.duration.map(|dur| dur.as_secs_f64()),
+                };
+                number.is_some_and(|number| compare_number(number, *op, *value))
+            }
+            Test::Played(period) => within(stats.map(|stats| stats.last_played), *period),
+            Test::Added(period) => within(track.added, *period),
+        };
+
+        result != self.negated
+    }
+}
+
+fn compare_text(text: &str, op: Op, value: &str) -> bool {
+    let (text, value) = (text.to_lowercase(), value.to_lowercase());
+    match op {
+        Op::Eq => text == value,
+        Op::Ne => text != value,
+        Op::Contains => text.contains(&value),
+        Op::Lt => text < value,
+        Op::Le => text <= value,
+        Op::Gt => text > value,
+        Op::Ge => text >= value,
+    }
+}
+
+fn compare_number(number: f64, op: Op, value: f64) -> bool {
+    match op {
+        Op::Eq => (number - value).abs() < f64::EPSILON,
+        Op::Ne => (number - value).abs() >= f64::EPSILON,
+        Op::Lt => number < value,
+        Op::Le => number <= value,
+        Op::Gt => number > value,
+        Op::Ge => number >= value,
+        Op::Contains => false,
+    }
+}
+
+fn parse_condition<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Condition> {
+    let mut word = tokens.next().ok_or_else(|| eyre!("missing condition"))?;
+    let negated = word.eq_ignore_ascii_case("not");
+    if negated {
+        word = tokens
+            .next()
+            .ok_or_else(|| eyre!("missing condition after NOT"))?;
+    }
+
+    let test = match word.to_lowercase().as_str() {
+        "played" => Test::Played(parse_period(tokens)?),
+        "added" => Test::Added(parse_period(tokens)?),
+        field => {
+            let op = parse_op(
+                tokens
+                    .next()
+                    .ok_or_else(|| eyre!("missing comparison after {field}"))?,
+            )?;
+            let value = tokens
+                .next()
+                .ok_or_else(|| eyre!("missing value after {field}"))?;
+            parse_comparison(field, op, value)?
+        }
+    };
+
+    Ok(Condition { negated, test })
+}
+
+fn parse_comparison(field: &str, op: Op, value: &str) -> Result<Test> {
+    let text_field = match field {
+        "title" => Some(TextField::Title),
+        "artist" => Some(TextField::Artist),
+        "album" => Some(TextField::Album),
+        "genre" => Some(TextField::Genre),
+        "path" => Some(TextField::Path),
+        _ => None,
+    };
+    if let Some(text_field) = text_field {
+        return Ok(Test::Text(text_field, op, value.to_string()));
+    }
+
+    let number_field = match field {
+        "year" => NumberField::Year,
+        "track" => NumberField::TrackNumber,
+        "rating" | "rated" => NumberField::Rating,
+        "plays" => NumberField::Plays,
+        "duration" | "length" => NumberField::Duration,
+        _ => return Err(eyre!("unknown field \"{field}\"")),
+    };
+    if op == Op::Contains {
+        return Err(eyre!("{field} is a number, it can't be searched with ~"));
+    }
+    let number = match number_field {
+        // Durations take the same forms as the go-to prompt, like 20m or 1:30:00
+        NumberField::Duration => player::parse_time(value)?.as_secs_f64(),
+        _ => value
+            .parse()
+            .map_err(|_| eyre!("{field} needs a number, found \"{value}\""))?,
+    };
+
+    Ok(Test::Number(number_field, op, number))
+}
+
+fn parse_op(token: &str) -> Result<Op> {
+    match token.to_lowercase().as_str() {
+        "=" | "==" | "is" => Ok(Op::Eq),
+        "!=" | "≠" => Ok(Op::Ne),
+        "<" => Ok(Op::Lt),
+        "<=" | "≤" => Ok(Op::Le),
+        ">" => Ok(Op::Gt),
+        ">=" | "≥" => Ok(Op::Ge),
+        "~" | "contains" => Ok(Op::Contains),
+        other => Err(eyre!("unknown comparison \"{other}\"")),
+    }
+}
+
+// `in 30 days`, `within 2 weeks`, `in the last 6 months`, `today` or `this week/month/year`.
+fn parse_period<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Period> {
+    let mut word = tokens
+        .next()
+        .ok_or_else(|| eyre!("missing time period"))?
+        .to_lowercase();
+
+    match word.as_str() {
+        "today" => return Ok(Period::Current(CalendarUnit::Day)),
+        "this" => {
+            let unit = tokens
+                .next()
+                .ok_or_else(|| eyre!("missing unit after \"this\""))?;
+            let unit = match unit.to_lowercase().as_str() {
+                "day" => CalendarUnit::Day,
+                "week" => CalendarUnit::Week,
+                "month" => CalendarUnit::Month,
+                "year" => CalendarUnit::Year,
+                other => {
+                    return Err(eyre!(
+                        "expected day, week, month or year, found \"{other}\""
+                    ));
+                }
+            };
+            return Ok(Period::Current(unit));
+        }
+        "in" | "within" => {}
+        other => {
+            return Err(eyre!(
+                "expected \"in\", \"within\" or \"this\", found \"{other}\""
+            ));
+        }
+    }
+
+    word = tokens
+        .next()
+        .ok_or_else(|| eyre!("missing time period"))?
+        .to_lowercase();
+    if word == "the" {
+        match tokens.next() {
+            Some(last) if last.eq_ignore_ascii_case("last") => {}
+            Some(other) => {
+                return Err(eyre!("expected \"last\" after \"the\", found \"{other}\""));
+            }
+            None => return Err(eyre!("missing \"last\" after \"the\"")),
+        }
+        word = tokens
+            .next()
+            .ok_or_else(|| eyre!("missing time period"))?
+            .to_lowercase();
+    }
+    let count: u32 = word.parse().map_err(|_| {
+        eyre!("expected a number of days, weeks, months or years, found \"{word}\"")
+    })?;
+    let unit = tokens
+        .next()
+        .ok_or_else(|| eyre!("missing unit after {count}"))?;
+
+    Ok(Period::Last(unit_length(unit)? * count))
+}
+
+// Midnight at the start of the day, week, month or year `now` falls in, in local time.
+// Weeks start on Monday.
+fn calendar_start(now: SystemTime, unit: CalendarUnit) -> Option<SystemTime> {
+    let mut tm = local_time(now)?;
+    tm.tm_sec = 0;
+    tm.tm_min = 0;
+    tm.tm_hour = 0;
+    match unit {
+        CalendarUnit::Day => {}
+        // mktime carries days before the 1st back into the previous month
+        CalendarUnit::Week => tm.tm_mday -= (tm.tm_wday + 6) % 7,
+        CalendarUnit::Month => tm.tm_mday = 1,
+        CalendarUnit::Year => {
+            tm.tm_mday = 1;
+            tm.tm_mon = 0;
+        }
+    }
+    // Midnight may fall on the other side of a daylight saving change
+    tm.tm_isdst = -1;
+
+    // SAFETY: `tm` is a valid, initialized struct tm
+    let start = unsafe { libc::mktime(&mut tm) };
+    let start = u64::try_from(start).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(start))
+}
+
+fn local_time(time: SystemTime) -> Option<libc::tm> {
+    let secs = libc::time_t::try_from(time.duration_since(UNIX_EPOCH).ok()?.as_secs()).ok()?;
+    // SAFETY: struct tm is plain data, all zeroes is a valid value for it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    let result = unsafe { libc::localtime_r(&secs, &mut tm) };
+
+    (!result.is_null()).then_some(tm)
+}
+
+// Months and years are taken as 30 and 365 days.
+fn unit_length(unit: &str) -> Result<Duration> {
+    match unit.to_lowercase().trim_end_matches('s') {
+        "hour" => Ok(Duration::from_secs(60 * 60)),
+        "day" => Ok(DAY),
+        "week" => Ok(DAY * 7),
+        "month" => Ok(DAY * 30),
+        "year" => Ok(DAY * 365),
+        other => Err(eyre!("unknown time unit \"{other}\"")),
+    }
+}
+
+// Words, quoted strings and comparison operators, which don't need spaces around them.
+fn tokenize(text: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut quoted = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => quoted.push(c),
+                    None => return Err(eyre!("unclosed quote")),
+                }
+            }
+            tokens.push(quoted);
+        } else if "=!<>~≤≥≠".contains(c) {
+            let mut op = String::new();
+            while let Some(&c) = chars.peek()
+                && "=!<>~≤≥≠".contains(c)
+            {
+                op.push(c);
+                chars.next();
+            }
+            tokens.push(op);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek()
+                && !c.is_whitespace()
+                && !"\"=!<>~≤≥≠".contains(c)
+            {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        }
+    }
+
+    Ok(tokens)
+}
+
+// The tracks a smart playlist picks from the library, in library order.
+pub fn evaluate(
+    rules: &Rules,
+    library: &[TrackInfo],
+    stats: &HashMap<PathBuf, PlayStats>,
+) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    library
+        .iter()
+        .filter(|track| rules.matches(track, stats.get(&track.path), now))
+        .map(|track| track.path.clone())
+        .collect()
+}
+
+// Rules are stored as plain text, long rules can be wrapped over several lines.
+// Lines starting with # are comments.
+pub fn load(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    Ok(lines.join(" "))
+}
+
+pub fn save(path: &Path, rules: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{}\n", rules.trim()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: Duration = Duration::from_secs(1_750_000_000);
+
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + NOW
+    }
+
+    fn days_ago(days: u32) -> Option<SystemTime> {
+        Some(now() - DAY * days)
+    }
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            path: PathBuf::from("/music/Miles Davis/Kind of Blue/01 So What.flac"),
+            title: Some("So What".to_string()),
+            artist: Some("Miles Davis".to_string()),
+            album: Some("Kind of Blue".to_string()),
+            track_number: Some(1),
+            genre: Some("Jazz".to_string()),
+            year: Some(1959),
+            rating: Some(4.0),
+            duration: Some(Duration::from_secs(9 * 60 + 22)),
+            added: days_ago(3),
+            ..Default::default()
+        }
+    }
+
+    fn stats(days: u32, plays: u32) -> PlayStats {
+        PlayStats {
+            last_played: days_ago(days).unwrap(),
+            plays,
+        }
+    }
+
+    fn matches(rules: &str, track: &TrackInfo, stats: Option<PlayStats>) -> bool {
+        Rules::parse(rules)
+            .unwrap_or_else(|e| panic!("{rules:?} should parse: {e}"))
+            .matches(track, stats.as_ref(), now())
+    }
+
+    #[test]
+    fn empty_rules_match_everything() {
+        assert!(matches("", &TrackInfo::default(), None));
+        assert!(matches("  ", &track(), None));
+    }
+
+    #[test]
+    fn compares_text_ignoring_case() {
+        let track = track();
+        assert!(matches("genre = jazz", &track, None));
+        assert!(matches("genre is JAZZ", &track, None));
+        assert!(matches("title = \"So What\"", &track, None));
+        assert!(matches("artist ~ davis", &track, None));
+        assert!(matches("album contains \"of blue\"", &track, None));
+        assert!(matches("path ~ \"Kind of Blue/\"", &track, None));
+        assert!(!matches("genre != Jazz", &track, None));
+        assert!(!matches("artist = Miles", &track, None));
+    }
+
+    #[test]
+    fn missing_fields_only_match_is_not() {
+        let track = TrackInfo::default();
+        assert!(!matches("genre = Jazz", &track, None));
+        assert!(!matches("genre ~ a", &track, None));
+        assert!(matches("genre != Jazz", &track, None));
+        assert!(!matches("year < 2000", &track, None));
+        assert!(!matches("rating >= 0", &track, None));
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let track = track();
+        assert!(matches("year < 1970", &track, None));
+        assert!(matches("year = 1959", &track, None));
+        assert!(!matches("year > 1959", &track, None));
+        assert!(matches("rating >= 4", &track, None));
+        assert!(matches("rated ≥ 4", &track, None));
+        assert!(!matches("rating > 4", &track, None));
+        assert!(matches("track = 1", &track, None));
+        assert!(matches("duration > 9m", &track, None));
+        assert!(matches("length < 9:30", &track, None));
+        assert!(!matches("duration > 20m", &track, None));
+    }
+
+    #[test]
+    fn operators_need_no_spaces() {
+        let track = track();
+        assert!(matches("rated≥4", &track, None));
+        assert!(matches("year<1970", &track, None));
+        assert!(matches("year<=1959", &track, None));
+        assert!(matches("genre=\"Jazz\"", &track, None));
+        assert!(matches("year≠1960", &track, None));
+        assert!(matches("artist~Miles", &track, None));
+    }
+
+    #[test]
+    fn counts_plays() {
+        let track = track();
+        assert!(matches("plays = 0", &track, None));
+        assert!(matches("plays > 2", &track, Some(stats(1, 3))));
+        assert!(!matches("plays = 0", &track, Some(stats(1, 3))));
+    }
+
+    #[test]
+    fn checks_when_tracks_were_played_and_added() {
+        let track = track();
+        assert!(matches("played in 30 days", &track, Some(stats(10, 1))));
+        assert!(!matches("played in 30 days", &track, Some(stats(40, 1))));
+        assert!(!matches("played in 30 days", &track, None));
+        assert!(matches("NOT played in 30 days", &track, None));
+        assert!(matches("not played in 30 days", &track, Some(stats(40, 1))));
+        assert!(!matches(
+            "NOT played in 30 days",
+            &track,
+            Some(stats(10, 1))
+        ));
+        assert!(matches("played today", &track, Some(stats(0, 1))));
+        assert!(matches("played within 2 weeks", &track, Some(stats(13, 1))));
+
+        assert!(matches("added in the last 6 months", &track, None));
+        assert!(!matches("added in the last 1 day", &track, None));
+        let old = TrackInfo {
+            added: days_ago(200),
+            ..track.clone()
+        };
+        assert!(!matches("added in the last 6 months", &old, None));
+        assert!(matches("added in the LAST 1 year", &old, None));
+        assert!(!matches("added today", &TrackInfo::default(), None));
+    }
+
+    #[test]
+    fn calendar_periods_start_at_local_midnight() {
+        for (unit, days) in [
+            (CalendarUnit::Day, 1),
+            (CalendarUnit::Week, 7),
+            (CalendarUnit::Month, 31),
+            (CalendarUnit::Year, 366),
+        ] {
+            let start = calendar_start(now(), unit).unwrap();
+            let age = now().duration_since(start).unwrap();
+            assert!(age < DAY * days, "{unit:?}");
+
+            let tm = local_time(start).unwrap();
+            assert_eq!((tm.tm_hour, tm.tm_min, tm.tm_sec), (0, 0, 0), "{unit:?}");
+            match unit {
+                CalendarUnit::Day => {}
+                CalendarUnit::Week => assert_eq!(tm.tm_wday, 1),
+                CalendarUnit::Month => assert_eq!(tm.tm_mday, 1),
+                CalendarUnit::Year => assert_eq!((tm.tm_mon, tm.tm_mday), (0, 1)),
+            }
+        }
+    }
+
+    #[test]
+    fn calendar_periods_end_at_their_start() {
+        let played_at = |time: SystemTime| PlayStats {
+            last_played: time,
+            plays: 1,
+        };
+        let added_at = |time: SystemTime| TrackInfo {
+            added: Some(time),
+            ..track()
+        };
+        let today = calendar_start(now(), CalendarUnit::Day).unwrap();
+        let week = calendar_start(now(), CalendarUnit::Week).unwrap();
+        let year = calendar_start(now(), CalendarUnit::Year).unwrap();
+        let second = Duration::from_secs(1);
+
+        assert!(matches("played today", &track(), Some(played_at(today))));
+        // Less than a day ago, but before midnight
+        assert!(!matches(
+            "played today",
+            &track(),
+            Some(played_at(today - second))
+        ));
+        assert!(matches("added this week", &added_at(week), None));
+        assert!(!matches("added this week", &added_at(week - second), None));
+        assert!(matches("added this year", &added_at(year), None));
+        assert!(!matches("added this year", &added_at(year - second), None));
+        assert!(matches(
+            "added in the last 1 year",
+            &added_at(year - second),
+            None
+        ));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let jazz = track();
+        let rock = TrackInfo {
+            genre: Some("Rock".to_string()),
+            year: Some(1968),
+            rating: Some(2.0),
+            ..track()
+        };
+        let new_rock = TrackInfo {
+            year: Some(1995),
+            ..rock.clone()
+        };
+
+        // Read as: genre = Jazz OR (year < 1970 AND rating >= 2)
+        let rules = "genre = Jazz OR year < 1970 AND rating >= 2";
+        assert!(matches(rules, &jazz, None));
+        assert!(matches(rules, &rock, None));
+        assert!(!matches(rules, &new_rock, None));
+
+        // Read as: (genre = Rock AND year > 1990) OR rating >= 4
+        let rules = "genre = Rock AND year > 1990 OR rating >= 4";
+        assert!(matches(rules, &jazz, None));
+        assert!(!matches(rules, &rock, None));
+        assert!(matches(rules, &new_rock, None));
+
+        // NOT applies to a single condition
+        let rules = "NOT genre = Jazz AND year < 1970";
+        assert!(!matches(rules, &jazz, None));
+        assert!(matches(rules, &rock, None));
+        assert!(!matches(rules, &new_rock, None));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rules in [
+            "genre",
+            "genre =",
+            "genre = Jazz AND",
+            "genre = Jazz OR",
+            "genre = Jazz year < 1970",
+            "mood = happy",
+            "year ~ 19",
+            "year > nineteen",
+            "duration > forever",
+            "genre = \"Jazz",
+            "genre <> Jazz",
+            "NOT",
+            "played",
+            "played yesterday",
+            "played in days",
+            "played in 2 fortnights",
+            "added this",
+            "added this fortnight",
+            "played in the past 3 days",
+            "played in the 3 days",
+            "played in the",
+        ] {
+            assert!(Rules::parse(rules).is_err(), "{rules:?} should not parse");
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use color_eyre::eyre::{Result, eyre};
+use lofty::{file::AudioFile, probe::Probe};
+use rodio::{Source, source::SeekError};
+use rust_ffmpeg::prelude::*;
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError},
+    thread,
+    time::Duration,
+};
+use tokio::{io::AsyncReadExt, runtime};
+
+// ffmpeg is asked for a fixed output layout, so the source never has to renegotiate it.
+const STREAM_CHANNELS: u16 = 2;
+const STREAM_SAMPLE_RATE: u32 = 44100;
+const CHUNK_SAMPLES: usize = 4096;
+// About three seconds of audio decoded ahead of playback.
+const BUFFERED_CHUNKS: usize = 64;
+// How long loading waits for ffmpeg to show it can decode the track.
+const FIRST_CHUNK_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Plays a track rodio cannot decode by having ffmpeg decode it to raw PCM on a pipe,
+// so playback starts right away instead of waiting for a whole file conversion.
+pub struct StreamSource {
+    track: PathBuf,
+    total: Option<Duration>,
+    receiver: Receiver<Vec<f32>>,
+    chunk: std::vec::IntoIter<f32>,
+    // Samples of silence still to play, filling in for a frame ffmpeg hasn't decoded in time.
+    silence: usize,
+}
+
+impl StreamSource {
+    pub fn new(track: PathBuf, start: Duration) -> Result<Self> {
+        if !track.is_file() {
+            return Err(eyre!("path is not a file"));
+        }
+
+        let total = Probe::open(&track)
+            .and_then(|probe| probe.read())
+            .map(|tagged_file| tagged_file.properties().duration())
+            .ok()
+            .filter(|dur| !dur.is_zero());
+
+        let receiver = spawn_decoder(track.clone(), start);
+        // Wait for the first samples here rather than on the audio thread, but not for long
+        let first_chunk = match receiver.recv_timeout(FIRST_CHUNK_TIMEOUT) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(eyre!("ffmpeg took too long to start decoding the track"));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(eyre!("ffmpeg could not decode the track"));
+            }
+        };
+
+        Ok(Self {
+            track,
+            total,
+            receiver,
+            chunk: first_chunk.into_iter(),
+            silence: 0,
+        })
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    // Never waits on ffmpeg, the mixer thread would stall every other sound with it.
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if self.silence > 0 {
+                self.silence -= 1;
+                return Some(0.0);
+            }
+            if let Some(sample) = self.chunk.next() {
+                return Some(sample);
+            }
+            match self.receiver.try_recv() {
+                Ok(chunk) => self.chunk = chunk.into_iter(),
+                // A whole frame of silence, so the channels stay in step
+                Err(TryRecvError::Empty) => self.silence = STREAM_CHANNELS as usize,
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+impl Source for StreamSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        STREAM_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        STREAM_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total
+    }
+
+    // Restarts ffmpeg at the requested offset,
+    // dropping the old receiver stops the previous decoder.
+    // Silence plays until the new decoder catches up.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let pos = match self.total {
+            Some(total) => pos.min(total),
+            None => pos,
+        };
+
+        self.receiver = spawn_decoder(self.track.clone(), pos);
+        // The rest of a frame that is partly played is kept, so the channels stay in step
+        let partial = self.chunk.len() % STREAM_CHANNELS as usize;
+        self.chunk = self
+            .chunk
+            .by_ref()
+            .take(partial)
+            .collect::<Vec<f32>>()
+            .into_iter();
+
+        Ok(())
+    }
+}
+
+fn spawn_decoder(track: PathBuf, start: Duration) -> Receiver<Vec<f32>> {
+    let (sender, receiver) = mpsc::sync_channel(BUFFERED_CHUNKS);
+    thread::spawn(move || {
+        let _ = run_ffmpeg(track, start, sender);
+    });
+
+    receiver
+}
+
+fn run_ffmpeg(track: PathBuf, start: Duration, sender: SyncSender<Vec<f32>>) -> Result<()> {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        let mut process = FFmpegBuilder::new()?
+            .input(Input::new(track).seek(start.into()))
+            .output(Output::new("pipe:1").format("f32le"))
+            .log_level(LogLevel::Error)
+            .audio_filter(AudioFilter::loudnorm())
+            .raw_args([
+                "-ac".to_string(),
+                STREAM_CHANNELS.to_string(),
+                "-ar".to_string(),
+                STREAM_SAMPLE_RATE.to_string(),
+            ])
+            .spawn()
+            .await?;
+
+        let mut stdout = process
+            .stdout()
+            .ok_or_else(|| eyre!("ffmpeg output unavailable"))?;
+        let mut bytes = vec![0u8; CHUNK_SAMPLES * 4];
+
+        loop {
+            let mut filled = 0;
+            while filled < bytes.len() {
+                let read = stdout.read(&mut bytes[filled..]).await?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+
+            let samples: Vec<f32> = bytes[..filled]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+
+            // The source went away, stop decoding
+            if !samples.is_empty() && sender.send(samples).is_err() {
+                process.kill().await?;
+                return Ok(());
+            }
+            if filled < bytes.len() {
+                break;
+            }
+        }
+
+        process.wait().await?;
+
+        Ok(())
+    })
+}
@@ -0,0 +1,353 @@
+use rodio::{Source, source::SeekError};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+pub const SPEED_STEP: f32 = 0.1;
+
+// Pitch is kept by overlapping windows of this length taken from the input at the sped up rate,
+// each one shifted by up to SEARCH_RANGE so its waveform lines up with the previous one.
+const WINDOW: Duration = Duration::from_millis(40);
+const SEARCH_RANGE: Duration = Duration::from_millis(10);
+// Only every few samples are compared when lining windows up, plenty for finding the best fit.
+const CORRELATION_STRIDE: usize = 4;
+// How many frames get resampled between checks for a new speed.
+const RESAMPLE_BLOCK: usize = 512;
+// Consumed input is dropped in batches rather than after every block.
+const TRIM_SLACK: usize = 8192;
+
+#[derive(Clone, Copy)]
+pub struct Tempo {
+    pub speed: f32,
+    pub preserve_pitch: bool,
+}
+
+impl Default for Tempo {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            preserve_pitch: true,
+        }
+    }
+}
+
+// Shared between the player and every source playing through TimeStretch,
+// so the speed carries over to each track loaded after it is changed.
+pub type SharedTempo = Arc<Mutex<Tempo>>;
+
+enum Mode {
+    // Reads through the input faster or slower, shifting the pitch along with the speed.
+    // At normal speed this passes samples through untouched.
+    Resample {
+        pos: f64,
+    },
+    // Changes the speed while keeping the pitch (WSOLA).
+    Stretch {
+        analysis: f64,
+        // Where the previous window started, None before the first one.
+        prev: Option<usize>,
+        // The fading out half of the previous window, added to the next one.
+        overlap: Vec<f32>,
+    },
+}
+
+// Plays a source at the shared speed. rodio's own speed control changes the sample rate,
+// which shifts the pitch and makes the sink's position jump whenever the speed changes.
+// Here the sample rate stays the same and the sink's position keeps counting time played.
+pub struct TimeStretch<S> {
+    source: S,
+    tempo: SharedTempo,
+    channels: usize,
+    sample_rate: u32,
+    // Half a window, in frames.
+    hop: usize,
+    search: usize,
+    // The rising half of a Hann window, the falling half is one minus it.
+    fade_in: Vec<f32>,
+    input: Vec<f32>,
+    // Index of the frame held at the start of `input`.
+    input_start: usize,
+    source_done: bool,
+    output: VecDeque<f32>,
+    mode: Mode,
+}
+
+impl<S: Source> TimeStretch<S> {
+    pub fn new(source: S, tempo: SharedTempo) -> Self {
+        let channels = source.channels().max(1) as usize;
+        let sample_rate = source.sample_rate();
+        let hop = ((sample_rate as f32 * WINDOW.as_secs_f32() / 2.0) as usize).max(1);
+        let search = (sample_rate as f32 * SEARCH_RANGE.as_secs_f32()) as usize;
+        let fade_in = (0..hop)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::PI * i as f32 / hop as f32).cos())
+            .collect();
+
+        Self {
+            source,
+            tempo,
+            channels,
+            sample_rate,
+            hop,
+            search,
+            fade_in,
+            input: Vec::new(),
+            input_start: 0,
+            source_done: false,
+            output: VecDeque::new(),
+            mode: Mode::Resample { pos: 0.0 },
+        }
+    }
+
+    fn current_speed(&self) -> f32 {
+        self.tempo.lock().unwrap().speed.clamp(MIN_SPEED, MAX_SPEED)
+    }
+
+    // Fills the output with the next block, returns false once the source has run out.
+    fn produce(&mut self) -> bool {
+        let tempo = *self.tempo.lock().unwrap();
+        let speed = tempo.speed.clamp(MIN_SPEED, MAX_SPEED) as f64;
+        let stretch = tempo.preserve_pitch && speed != 1.0;
+
+        // Switching modes picks up right where the other mode left off, so it doesn't click
+        let switched = match (&self.mode, stretch) {
+            (Mode::Resample { pos }, true) => Some(Mode::Stretch {
+                analysis: *pos,
+                prev: None,
+                overlap: Vec::new(),
+            }),
+            (Mode::Stretch { analysis, prev, .. }, false) => Some(Mode::Resample {
+                pos: prev.map_or(*analysis, |prev| (prev + self.hop) as f64),
+            }),
+            _ => None,
+        };
+        if let Some(mode) = switched {
+            self.mode = mode;
+        }
+
+        match self.mode {
+            Mode::Resample { .. } => self.resample_block(speed),
+            Mode::Stretch { .. } => self.stretch_block(speed),
+        }
+    }
+
+    fn resample_block(&mut self, speed: f64) -> bool {
+        let Mode::Resample { mut pos } = self.mode else {
+            return false;
+        };
+
+        for _ in 0..RESAMPLE_BLOCK {
+            let frame = pos.floor() as usize;
+            self.fill(frame + 2);
+            if frame >= self.input_end() {
+                break;
+            }
+
+            let fraction = (pos - frame as f64) as f32;
+            for ch in 0..self.channels {
+                let a = self.sample(frame, ch);
+                let b = self.sample(frame + 1, ch);
+                self.output.push_back(a + (b - a) * fraction);
+            }
+            pos += speed;
+        }
+
+        self.mode = Mode::Resample { pos };
+        self.trim(pos.floor() as usize);
+
+        !self.output.is_empty()
+    }
+
+    fn stretch_block(&mut self, speed: f64) -> bool {
+        let Mode::Stretch {
+            analysis,
+            prev,
+            ref mut overlap,
+        } = self.mode
+        else {
+            return false;
+        };
+        let overlap = std::mem::take(overlap);
+        let hop = self.hop;
+        let nominal = analysis.round() as usize;
+
+        let start = match prev {
+            None => nominal,
+            Some(prev) => {
+                let lo = nominal.saturating_sub(self.search).max(self.input_start);
+                let hi = (nominal + self.search).max(lo);
+                self.fill((hi + hop).max(prev + hop * 2));
+                self.best_alignment(prev + hop, nominal, lo, hi)
+            }
+        };
+        self.fill(start + hop * 2);
+
+        // The source ran out, finish with what is left of the last window
+        if start >= self.input_end() {
+            self.output.extend(overlap);
+            self.mode = Mode::Stretch {
+                analysis,
+                prev,
+                overlap: Vec::new(),
+            };
+            return !self.output.is_empty();
+        }
+
+        for i in 0..hop {
+            for ch in 0..self.channels {
+                let sample = self.sample(start + i, ch);
+                let mixed = match prev {
+                    // The first window continues straight from the input, nothing to fade into
+                    None => sample,
+                    Some(_) => overlap[i * self.channels + ch] + self.fade_in[i] * sample,
+                };
+                self.output.push_back(mixed);
+            }
+        }
+
+        let mut next_overlap = Vec::with_capacity(hop * self.channels);
+        for i in 0..hop {
+            for ch in 0..self.channels {
+                next_overlap.push((1.0 - self.fade_in[i]) * self.sample(start + hop + i, ch));
+            }
+        }
+
+        let next_analysis = analysis + hop as f64 * speed;
+        self.mode = Mode::Stretch {
+            analysis: next_analysis,
+            prev: Some(start),
+            overlap: next_overlap,
+        };
+        self.trim(start.min((next_analysis as usize).saturating_sub(self.search)));
+
+        true
+    }
+
+    // The window start between lo and hi whose waveform best matches the natural
+    // continuation of the previous window, preferring the nominal start on ties.
+    fn best_alignment(&self, natural: usize, nominal: usize, lo: usize, hi: usize) -> usize {
+        let target: Vec<f32> = (0..self.hop)
+            .step_by(CORRELATION_STRIDE)
+            .map(|i| self.mono(natural + i))
+            .collect();
+        let region: Vec<f32> = (lo..hi + self.hop).map(|frame| self.mono(frame)).collect();
+
+        let score = |candidate: usize| -> f32 {
+            let offset = candidate - lo;
+            let (mut dot, mut energy) = (0.0, 0.0);
+            for (k, t) in target.iter().enumerate() {
+                let x = region[offset + k * CORRELATION_STRIDE];
+                dot += t * x;
+                energy += x * x;
+            }
+            dot / energy.sqrt().max(f32::EPSILON)
+        };
+
+        let mut best = (nominal.clamp(lo, hi), score(nominal.clamp(lo, hi)));
+        for candidate in lo..=hi {
+            let candidate_score = score(candidate);
+            if candidate_score > best.1 + f32::EPSILON {
+                best = (candidate, candidate_score);
+            }
+        }
+
+        best.0
+    }
+
+    // Reads from the source until frames up to `end` are buffered or it runs out.
+    fn fill(&mut self, end: usize) {
+        while self.input_end() < end && !self.source_done {
+            for _ in 0..self.channels {
+                match self.source.next() {
+                    Some(sample) => self.input.push(sample),
+                    None => {
+                        self.source_done = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn trim(&mut self, keep_from: usize) {
+        if keep_from > self.input_start + TRIM_SLACK {
+            let frames = (keep_from - self.input_start).min(self.input.len() / self.channels);
+            self.input.drain(..frames * self.channels);
+            self.input_start += frames;
+        }
+    }
+
+    fn input_end(&self) -> usize {
+        self.input_start + self.input.len() / self.channels
+    }
+
+    // Frames outside the buffered input read as silence.
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        if frame < self.input_start {
+            return 0.0;
+        }
+        let idx = (frame - self.input_start) * self.channels + channel;
+        self.input.get(idx).copied().unwrap_or(0.0)
+    }
+
+    fn mono(&self, frame: usize) -> f32 {
+        (0..self.channels).map(|ch| self.sample(frame, ch)).sum()
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.input_start = 0;
+        self.source_done = false;
+        self.output.clear();
+        self.mode = Mode::Resample { pos: 0.0 };
+    }
+}
+
+impl<S: Source> Iterator for TimeStretch<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if let Some(sample) = self.output.pop_front() {
+                return Some(sample);
+            }
+            if !self.produce() {
+                return None;
+            }
+        }
+    }
+}
+
+impl<S: Source> Source for TimeStretch<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let speed = self.current_speed();
+        self.source
+            .total_duration()
+            .map(|total| total.div_f32(speed))
+    }
+
+    // Positions given to the sink are in time played, the track itself is
+    // further along or behind by the speed.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let speed = self.current_speed();
+        self.source.try_seek(pos.mul_f32(speed))?;
+        self.reset();
+
+        Ok(())
+    }
+}
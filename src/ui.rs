@@ -1,12 +1,23 @@
 use ratatui::{
     Frame,
     layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
-    style::{Color, Stylize},
+    style::{Color, Style, Stylize},
     text::ToSpan,
-    widgets::{Block, Paragraph, Widget},
+    widgets::{Block, List, ListState, Paragraph, Widget},
 };
 
-use crate::{app::App, player::Status};
+use std::{path::Path, time::Duration};
+
+use crate::{
+    app::{AbRepeat, App, Panel, RepeatMode},
+    convert::{ConversionMark, ConversionState},
+    eq::{BAND_COUNT, BAND_FREQUENCIES, GAIN_LIMIT},
+    gain::GainMode,
+    player::Status,
+    playlist::Entry,
+};
+
+const PROGRESS_BAR_MAX_WIDTH: u16 = 60;
 
 pub fn render(app: &App, frame: &mut Frame) {
     let outer_layout = Layout::default()
@@ -29,13 +40,27 @@ pub fn render(app: &App, frame: &mut Frame) {
         .constraints(vec![Constraint::Percentage(60), Constraint::Percentage(40)])
         .split(inner_layout[1]);
 
-    let left_panel_chunks = Layout::default()
+    let left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Percentage(65), Constraint::Percentage(35)])
+        .split(inner_layout[0]);
+
+    let queue_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints(vec![Constraint::Percentage(100)])
         .margin(2)
-        .split(inner_layout[0]);
+        .split(left_chunks[0]);
 
-    frame.render_widget(get_queue_para(app), left_panel_chunks[0]);
+    draw_queue(app, frame, queue_chunks[0]);
+
+    let playlist_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Percentage(100)])
+        .margin(1)
+        .horizontal_margin(2)
+        .split(left_chunks[1]);
+
+    draw_playlists(app, frame, playlist_chunks[0]);
 
     Block::bordered()
         .fg(Color::White)
@@ -43,17 +68,38 @@ pub fn render(app: &App, frame: &mut Frame) {
         .title_alignment(Alignment::Right)
         .render(main_chunks[0], frame.buffer_mut());
 
+    let lower_title = match app.panel {
+        Panel::Player => "Control",
+        Panel::Equalizer => "Equalizer",
+        Panel::History => "History",
+        Panel::Queue => "Queue",
+        Panel::Playlists => "Playlists",
+    };
+
     Block::bordered()
         .fg(Color::White)
-        .title("Control")
+        .title(lower_title)
         .title_alignment(Alignment::Right)
         .render(main_chunks[1], frame.buffer_mut());
 
     Block::bordered()
         .fg(Color::White)
-        .title("Queue")
+        .border_style(get_focus_style(app, Panel::Queue))
+        .title(format!("Queue ({})", app.track_queue.len()))
         .title_alignment(Alignment::Left)
-        .render(inner_layout[0], frame.buffer_mut());
+        .render(left_chunks[0], frame.buffer_mut());
+
+    let playlists_title = match &app.playlist_edit {
+        Some(edit) => format!("Playlist: {}", edit.name),
+        None => "Playlists".into(),
+    };
+
+    Block::bordered()
+        .fg(Color::White)
+        .border_style(get_focus_style(app, Panel::Playlists))
+        .title(playlists_title)
+        .title_alignment(Alignment::Left)
+        .render(left_chunks[1], frame.buffer_mut());
 
     let player_chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -70,7 +116,13 @@ pub fn render(app: &App, frame: &mut Frame) {
         .horizontal_margin(3)
         .split(main_chunks[1]);
 
-    draw_controls(frame, control_chunks[0]);
+    match app.panel {
+        Panel::Player => draw_controls(app, frame, control_chunks[0]),
+        Panel::Equalizer => draw_equalizer(app, frame, control_chunks[0]),
+        Panel::History => draw_history(app, frame, control_chunks[0]),
+        Panel::Queue => draw_queue_controls(frame, control_chunks[0]),
+        Panel::Playlists => draw_playlist_controls(app, frame, control_chunks[0]),
+    }
 }
 
 fn draw_player(app: &App, frame: &mut Frame, chunk: Rect) {
@@ -78,11 +130,24 @@ fn draw_player(app: &App, frame: &mut Frame, chunk: Rect) {
         get_track_name_str(app),
         "".into(),
         get_track_pos_str(app),
+        get_progress_bar_str(app, chunk.width),
         "".into(),
         get_status_str(app),
-        get_loop_status_str(app),
+        get_conversion_str(app),
+        format!(
+            "{} {}",
+            get_loop_status_str(app),
+            get_shuffle_status_str(app)
+        )
+        .trim()
+        .to_string(),
+        get_gapless_status_str(app),
+        get_crossfade_status_str(app),
+        get_streaming_status_str(app),
         get_info_str(app),
-        get_volume_str(app),
+        format!("{}   {}", get_volume_str(app), get_speed_str(app)),
+        get_gain_str(app),
+        get_eq_str(app),
     ];
 
     let area = center_vertical(chunk, player_text.len() as u16);
@@ -94,24 +159,73 @@ fn draw_player(app: &App, frame: &mut Frame, chunk: Rect) {
     frame.render_widget(player_para, area);
 }
 
-fn draw_controls(frame: &mut Frame, chunk: Rect) {
+fn draw_controls(app: &App, frame: &mut Frame, chunk: Rect) {
+    let seek_step = format!(" Seek {}s <Shift+←/→>", app.seek_step.as_secs());
     let controls = vec![
         " Play/Pause <Space>",
         " Load Now <N>",
         " Queue <Q>",
         " Queue Folder <D>",
+        " Open Playlist <O>",
+        " Save Playlist <W>",
         " Skip <S>",
+        " Cancel Convert <C>",
+        " Stream Mode <M>",
         " Rewind/Seek <←/→>",
+        seek_step.as_str(),
+        " Seek Step <Shift+↑/↓>",
+        " Go To <J>",
+        " Jump 0-90% <0-9>",
+        " Chapter <PgUp/PgDn>",
         " Volume <↑/↓>",
-        " Loop <L>",
+        " Repeat Mode <L>",
+        " A-B Repeat <B>",
+        " Shuffle <H>",
+        " Shuffle Queue Now <Shift+H>",
+        " Previous <Z>",
+        " History <Y>",
+        " Edit Queue <Tab>",
+        " Playlists <V>",
+        " Gapless <G>",
+        " Crossfade <X>",
+        " Fade Length <[/]>",
+        " ReplayGain <R>",
+        " Preamp <-/=>",
+        " Scan Loudness <A>",
+        " Rescan Library <U>",
+        " Equalizer <E>",
+        " Speed <,/.>",
+        " Keep Pitch <P>",
         " Quit <Esc>",
     ];
 
+    // Controls that don't fit the panel are split into pages, flipped through with ?
+    let fitting_rows = |height: u16| usize::from(height).div_ceil(2);
+    let (area, page) = if controls.len().div_ceil(4) <= fitting_rows(chunk.height) {
+        (chunk, controls.as_slice())
+    } else {
+        let [grid_area, hints] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)])
+            .spacing(1)
+            .areas(chunk);
+        let per_page = fitting_rows(grid_area.height).max(1) * 4;
+        let pages = controls.len().div_ceil(per_page);
+        let current = app.controls_page % pages;
+        frame.render_widget(
+            Paragraph::new(format!(" More Controls <?> ({}/{pages})", current + 1)),
+            hints,
+        );
+        let start = current * per_page;
+        (
+            grid_area,
+            &controls[start..(start + per_page).min(controls.len())],
+        )
+    };
+
     let rows = Layout::default()
         .direction(Direction::Vertical)
-        .constraints(vec![Constraint::Length(1); 4])
+        .constraints(vec![Constraint::Length(1); page.len().div_ceil(4)])
         .spacing(1)
-        .split(chunk);
+        .split(area);
 
     let grid: Vec<Rect> = rows
         .iter()
@@ -125,11 +239,192 @@ fn draw_controls(frame: &mut Frame, chunk: Rect) {
         })
         .collect();
 
-    for (idx, control) in controls.iter().enumerate() {
+    for (idx, control) in page.iter().enumerate() {
         frame.render_widget(Paragraph::new(*control), grid[idx])
     }
 }
 
+fn draw_equalizer(app: &App, frame: &mut Frame, chunk: Rect) {
+    let [header, bands, hints] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Fill(1),
+        Constraint::Length(1),
+    ])
+    .spacing(1)
+    .areas(chunk);
+
+    frame.render_widget(Paragraph::new(get_eq_str(app)), header);
+
+    let gains = *app.effects.eq.lock().unwrap();
+    let columns =
+        Layout::horizontal(vec![Constraint::Ratio(1, BAND_COUNT as u32); BAND_COUNT]).split(bands);
+
+    for (idx, column) in columns.iter().enumerate() {
+        // Two rows are left for the gain and frequency labels
+        let mut lines = get_eq_meter(gains[idx], column.height.saturating_sub(2));
+        lines.push(format!("{:+.0}", gains[idx]));
+        lines.push(format_frequency(BAND_FREQUENCIES[idx]));
+
+        let mut band_para = Paragraph::new(lines.join("\n")).centered();
+        if idx == app.eq_band {
+            band_para = band_para.fg(Color::Yellow);
+        }
+        frame.render_widget(band_para, *column);
+    }
+
+    frame.render_widget(
+        Paragraph::new(
+            " Band <←/→>   Gain <↑/↓>   Preset <P>   Save Preset <W>   Delete Preset <Del>   Close <E/Esc>",
+        ),
+        hints,
+    );
+}
+
+fn draw_queue_controls(frame: &mut Frame, chunk: Rect) {
+    let controls = [
+        " Select <↑/↓, PgUp/PgDn, Home/End>",
+        " Move Entry <Shift+↑/↓>",
+        " Play Now <Enter>",
+        " Play Next <N>",
+        " Remove <Del>",
+        " Clear Queue <C>",
+        " Playlists <Tab>",
+        " Back <Esc>",
+    ];
+
+    frame.render_widget(Paragraph::new(controls.join("\n")), chunk);
+}
+
+fn draw_playlist_controls(app: &App, frame: &mut Frame, chunk: Rect) {
+    let controls = match app.playlist_edit {
+        Some(_) => vec![
+            " Select <↑/↓>",
+            " Move Entry <Shift+↑/↓>",
+            " Play Now <Enter>",
+            " Remove <Del>",
+            " Back to Playlists <←/Esc>",
+        ],
+        None => vec![
+            " Select <↑/↓>",
+            " Open, Replacing the Queue <Enter>",
+            " Open, Appending to the Queue <A>",
+            " Edit Entries or Rules <→/E>",
+            " New <N>   New Smart <S>   Rename <R>   Delete <Del>",
+            " Add Current Track <C>   Add Whole Queue <Q>",
+            " Back <Tab/Esc>",
+        ],
+    };
+
+    frame.render_widget(Paragraph::new(controls.join("\n")), chunk);
+}
+
+// Saved playlists, or the entries of the one open for editing.
+fn draw_playlists(app: &App, frame: &mut Frame, chunk: Rect) {
+    let (rows, cursor): (Vec<String>, usize) = match &app.playlist_edit {
+        Some(edit) => (
+            edit.entries
+                .iter()
+                .map(|entry| {
+                    let mut row = match &entry.title {
+                        Some(title) => title.clone(),
+                        None => get_file_name_str(&entry.path),
+                    };
+                    if !entry.path.is_file() {
+                        row.push_str(" (missing)");
+                    }
+                    row
+                })
+                .collect(),
+            edit.cursor,
+        ),
+        None => (
+            app.playlists
+                .iter()
+                .map(|saved| match saved.smart {
+                    true => format!("{} (smart)", saved.name),
+                    false => saved.name.clone(),
+                })
+                .collect(),
+            app.playlist_cursor,
+        ),
+    };
+
+    if rows.is_empty() {
+        let empty = match app.playlist_edit {
+            Some(_) => "Empty playlist",
+            None => "No playlists yet",
+        };
+        frame.render_widget(Paragraph::new(empty), chunk);
+        return;
+    }
+
+    let list = List::new(rows).highlight_style(Style::new().fg(Color::Yellow));
+    let mut state = ListState::default();
+    if app.panel == Panel::Playlists {
+        state.select(Some(cursor));
+    }
+
+    frame.render_stateful_widget(list, chunk, &mut state);
+}
+
+fn get_focus_style(app: &App, panel: Panel) -> Style {
+    match app.panel == panel {
+        true => Style::new().fg(Color::Yellow),
+        false => Style::new().fg(Color::White),
+    }
+}
+
+// Most recently played first.
+fn draw_history(app: &App, frame: &mut Frame, chunk: Rect) {
+    let [list, hints] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)])
+        .spacing(1)
+        .areas(chunk);
+
+    let lines: Vec<String> = match app.history.is_empty() {
+        true => vec!["Nothing played yet".into()],
+        false => app
+            .history
+            .iter()
+            .rev()
+            .take(list.height as usize)
+            .enumerate()
+            .map(|(idx, path)| format!("{:>3}. {}", idx + 1, get_file_name_str(path)))
+            .collect(),
+    };
+
+    frame.render_widget(Paragraph::new(lines.join("\n")), list);
+    frame.render_widget(Paragraph::new(" Previous Track <Z>   Close <Y/Esc>"), hints);
+}
+
+// A vertical bar for one band, growing up or down from the middle row at 0 dB.
+fn get_eq_meter(gain: f32, rows: u16) -> Vec<String> {
+    let mid = rows.saturating_sub(1) / 2;
+    if mid == 0 {
+        return Vec::new();
+    }
+    let step = GAIN_LIMIT / mid as f32;
+
+    (0..=mid * 2)
+        .map(|row| {
+            let level = (mid as f32 - row as f32) * step;
+            let filled = (level > 0.0 && gain >= level - step / 2.0)
+                || (level < 0.0 && gain <= level + step / 2.0);
+            match (row == mid, filled) {
+                (true, _) => "───".into(),
+                (false, true) => "███".into(),
+                (false, false) => "".into(),
+            }
+        })
+        .collect()
+}
+
+fn get_file_name_str(path: &Path) -> String {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("[Invalid UTF-8 name]")
+        .to_string()
+}
+
 fn get_track_name_str(app: &App) -> String {
     match app.track_path.clone() {
         Some(path) => {
@@ -147,8 +442,58 @@ fn get_track_name_str(app: &App) -> String {
     }
 }
 
+// Positions are in track time, with the time left to play added when the speed changes it.
 fn get_track_pos_str(app: &App) -> String {
-    app.track_pos_as_str()
+    let pos = app.track_pos.unwrap_or_default();
+    let Some(dur) = app.track_duration else {
+        return format_duration(pos);
+    };
+
+    let speed = app.tempo().speed;
+    match speed == 1.0 {
+        true => format!("{} / {}", format_duration(pos), format_duration(dur)),
+        false => format!(
+            "{} / {} ({} left at {speed:.1}x)",
+            format_duration(pos),
+            format_duration(dur),
+            format_duration(dur.saturating_sub(pos).div_f32(speed))
+        ),
+    }
+}
+
+// The track's progress with the A-B repeat points marked on it.
+fn get_progress_bar_str(app: &App, width: u16) -> String {
+    let Some(dur) = app.track_duration.filter(|dur| !dur.is_zero()) else {
+        return "".into();
+    };
+    let width = width.saturating_sub(4).min(PROGRESS_BAR_MAX_WIDTH) as usize;
+    if width == 0 {
+        return "".into();
+    }
+
+    let cell = |pos: Duration| -> usize {
+        let fraction = (pos.as_secs_f32() / dur.as_secs_f32()).clamp(0.0, 1.0);
+        ((fraction * width as f32) as usize).min(width - 1)
+    };
+
+    let played = cell(app.track_pos.unwrap_or_default());
+    let mut bar: Vec<char> = (0..width)
+        .map(|idx| match idx <= played {
+            true => '━',
+            false => '─',
+        })
+        .collect();
+
+    match app.ab_repeat {
+        AbRepeat::Off => {}
+        AbRepeat::Start(start) => bar[cell(start)] = 'A',
+        AbRepeat::Loop(start, end) => {
+            bar[cell(start)] = 'A';
+            bar[cell(end)] = 'B';
+        }
+    }
+
+    bar.into_iter().collect()
 }
 
 fn get_status_str(app: &App) -> String {
@@ -159,9 +504,73 @@ fn get_status_str(app: &App) -> String {
     }
 }
 
+fn get_conversion_str(app: &App) -> String {
+    let Some(conversion) = &app.conversion else {
+        return "".into();
+    };
+
+    let name = conversion
+        .track
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("[Invalid UTF-8 name]");
+
+    match (conversion.progress(), conversion.state()) {
+        (Some(progress), _) => format!("Converting {name}: {}%", (progress * 100.0) as i32),
+        (None, ConversionState::Running(processed)) => {
+            format!(
+                "Converting {name}: {} processed",
+                format_duration(processed)
+            )
+        }
+        (None, _) => format!("Converting {name}"),
+    }
+}
+
 fn get_loop_status_str(app: &App) -> String {
-    match app.looping {
-        true => "[Looped]".into(),
+    let ab_repeat = match app.ab_repeat {
+        AbRepeat::Off => "".into(),
+        AbRepeat::Start(start) => format!("[A-B from {}]", format_duration(start)),
+        AbRepeat::Loop(start, end) => format!(
+            "[A-B {} - {}]",
+            format_duration(start),
+            format_duration(end)
+        ),
+    };
+
+    let repeat = match app.repeat {
+        RepeatMode::Off => return ab_repeat,
+        RepeatMode::One => "[Repeat One]",
+        RepeatMode::Queue => "[Repeat Queue]",
+    };
+
+    format!("{repeat} {ab_repeat}").trim_end().into()
+}
+
+fn get_shuffle_status_str(app: &App) -> String {
+    match app.shuffle {
+        true => "[Shuffle]".into(),
+        false => "".into(),
+    }
+}
+
+fn get_gapless_status_str(app: &App) -> String {
+    match app.gapless {
+        true => "[Gapless]".into(),
+        false => "".into(),
+    }
+}
+
+fn get_crossfade_status_str(app: &App) -> String {
+    match app.crossfade {
+        true => format!("[Crossfade {}s]", app.crossfade_dur.as_secs()),
+        false => "".into(),
+    }
+}
+
+fn get_streaming_status_str(app: &App) -> String {
+    match app.streaming {
+        true => "[Streaming]".into(),
         false => "".into(),
     }
 }
@@ -170,26 +579,101 @@ fn get_volume_str(app: &App) -> String {
     format!("Volume: {}%", (app.volume * 100.00).ceil() as i32)
 }
 
+fn get_speed_str(app: &App) -> String {
+    let tempo = app.tempo();
+    match tempo.preserve_pitch {
+        true => format!("Speed: {:.1}x (pitch kept)", tempo.speed),
+        false => format!("Speed: {:.1}x", tempo.speed),
+    }
+}
+
+fn get_gain_str(app: &App) -> String {
+    let mode = match app.gain_mode {
+        GainMode::Off => return "ReplayGain: off".into(),
+        GainMode::Track => "track",
+        GainMode::Album => "album",
+    };
+
+    match app.gain_db() {
+        Some(db) => format!(
+            "ReplayGain ({mode}): {db:+.1} dB, preamp {:+.0} dB",
+            app.preamp
+        ),
+        None => format!("ReplayGain ({mode}): no gain tags"),
+    }
+}
+
+fn get_eq_str(app: &App) -> String {
+    match app.eq_preset {
+        Some(idx) => format!("EQ: {}", app.eq_presets[idx].name),
+        None => "EQ: custom".into(),
+    }
+}
+
+// An open prompt takes the place of the last message.
 fn get_info_str(app: &App) -> String {
+    if let Some(prompt) = &app.prompt {
+        return format!("{}: {}_", prompt.action.label(), prompt.input);
+    }
+
     match app.info.last() {
         Some(str) => str.clone(),
         None => "".into(),
     }
 }
 
-fn get_queue_para(app: &App) -> Paragraph<'static> {
+// The cursor is only shown while the queue has focus, the list scrolls to keep it in view.
+fn draw_queue(app: &App, frame: &mut Frame, chunk: Rect) {
     let mut track_vec: Vec<String> = Vec::new();
-    for track in app.track_queue.clone() {
-        if let Some(track_name) = track.file_name().unwrap().to_str() {
-            track_vec.push(track_name.to_string());
-        } else {
-            track_vec.push("[Invalid UTF-8 name]".into());
+    for track in app.track_queue.iter() {
+        let mut row = match app.playlist_entries.get(track) {
+            Some(Entry {
+                title: Some(title), ..
+            }) => title.clone(),
+            _ => get_file_name_str(track),
+        };
+
+        match app.conversion_marks.get(track) {
+            Some(ConversionMark::Pending) => row.push_str(" (pending conversion)"),
+            Some(ConversionMark::Ready) => row.push_str(" (ready)"),
+            Some(ConversionMark::Failed) => row.push_str(" (failed)"),
+            Some(ConversionMark::Native) | None => {}
+        }
+
+        match app.resume_points.get(track) {
+            Some(point) if point.finished => row.push_str(" (finished)"),
+            Some(point) => row.push_str(&format!(
+                " (at {}, {:.0}%)",
+                format_duration(point.position),
+                point.progress() * 100.0
+            )),
+            None => {}
         }
+
+        track_vec.push(row);
+    }
+
+    let list = List::new(track_vec).highlight_style(Style::new().fg(Color::Yellow));
+    let mut state = ListState::default();
+    if app.panel == Panel::Queue && !app.track_queue.is_empty() {
+        state.select(Some(app.queue_cursor));
     }
 
-    let tracks_str = track_vec.join("\n");
+    frame.render_stateful_widget(list, chunk, &mut state);
+}
+
+fn format_duration(duration: Duration) -> String {
+    let sec = duration.as_secs() % 60;
+    let min = duration.as_secs() / 60;
 
-    Paragraph::new(tracks_str)
+    format!("{:02}:{:02}", min, sec)
+}
+
+fn format_frequency(freq: f32) -> String {
+    match freq >= 1000.0 {
+        true => format!("{}k", freq / 1000.0),
+        false => format!("{freq}"),
+    }
 }
 
 fn center_vertical(area: Rect, height: u16) -> Rect {